@@ -0,0 +1,192 @@
+//! Date formatting for the date types, mirroring libmagic's
+//! `file_fmtdatetime`, `file_fmtdate` and `file_fmttime`.
+
+/// The largest time libmagic is willing to format, which is in the year
+/// 3000.
+const MAX_CTIME: i64 = 0x3afff487cf;
+
+/// The number of seconds between the Windows FILETIME epoch of 1601-01-01
+/// and the Unix epoch.
+const FILETIME_EPOCH: i64 = 11644473600;
+
+/// FILETIME counts in units of 100 nanoseconds.
+const FILETIME_PER_SEC: i64 = 10_000_000;
+
+const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct",
+    "Nov", "Dec",
+];
+
+/// How the value of a date type is interpreted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Clock {
+    /// Seconds since the Unix epoch, displayed in UTC.
+    Utc,
+    /// Seconds since the Unix epoch, displayed in local time.
+    Local,
+    /// A Windows FILETIME, displayed in UTC.
+    Windows,
+}
+
+/// The broken down time, like a `struct tm`.
+struct Tm {
+    year: i64,
+    /// 0-11
+    mon: usize,
+    mday: u32,
+    hour: u32,
+    min: u32,
+    sec: u32,
+    /// 0-6 where 0 is Sunday
+    wday: usize,
+}
+
+/// Format a timestamp like `asctime(3)` without the trailing newline, e.g.,
+/// "Thu Jan  1 00:00:00 1970".
+pub(crate) fn format_datetime(value: i64, clock: Clock) -> String {
+    let time = match clock {
+        Clock::Windows => value / FILETIME_PER_SEC - FILETIME_EPOCH,
+        Clock::Utc | Clock::Local => value,
+    };
+
+    if time > MAX_CTIME {
+        return "*Invalid datetime*".to_string();
+    }
+
+    let tm = match clock {
+        Clock::Local => match localtime(time) {
+            Some(tm) => tm,
+            None => return "*Invalid datetime*".to_string(),
+        },
+        Clock::Utc | Clock::Windows => gmtime(time),
+    };
+
+    format!(
+        "{} {}{:3} {:02}:{:02}:{:02} {}",
+        DAYS[tm.wday],
+        MONTHS[tm.mon],
+        tm.mday,
+        tm.hour,
+        tm.min,
+        tm.sec,
+        tm.year
+    )
+}
+
+/// Format an MS-DOS packed date like libmagic's `strftime(3)` format of
+/// "%a, %b %d %Y". libmagic never computes the day of the week so it's
+/// always Sunday.
+pub(crate) fn format_dos_date(value: u16) -> String {
+    let mday = value & 0x1f;
+    let mon = ((value >> 5) & 0xf) as usize;
+    let year = (value >> 9) as u32 + 1980;
+    let month = match mon {
+        1..=12 => MONTHS[mon - 1],
+        _ => "?",
+    };
+    format!("Sun, {} {:02} {}", month, mday, year)
+}
+
+/// Format an MS-DOS packed time like `strftime(3)`'s "%T".
+pub(crate) fn format_dos_time(value: u16) -> String {
+    let sec = (value & 0x1f) * 2;
+    let min = (value >> 5) & 0x3f;
+    let hour = value >> 11;
+    format!("{:02}:{:02}:{:02}", hour, min, sec)
+}
+
+fn gmtime(time: i64) -> Tm {
+    let days = time.div_euclid(86400);
+    let secs = time.rem_euclid(86400) as u32;
+
+    // Convert days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let mday = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let mon = if mp < 10 { mp + 2 } else { mp - 10 } as usize;
+    let year = yoe + era * 400 + i64::from(mon < 2);
+
+    Tm {
+        year,
+        mon,
+        mday,
+        hour: secs / 3600,
+        min: secs / 60 % 60,
+        sec: secs % 60,
+        // 1970-01-01 was a Thursday
+        wday: (days + 4).rem_euclid(7) as usize,
+    }
+}
+
+fn localtime(time: i64) -> Option<Tm> {
+    let time = libc::time_t::try_from(time).ok()?;
+    // SAFETY: localtime_r only writes to the tm we pass it, which is valid
+    // for writes, and a zeroed tm is a valid value for it to start from.
+    let tm = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return None;
+        }
+        tm
+    };
+
+    Some(Tm {
+        year: tm.tm_year as i64 + 1900,
+        mon: usize::try_from(tm.tm_mon).ok()?.min(11),
+        mday: tm.tm_mday as u32,
+        hour: tm.tm_hour as u32,
+        min: tm.tm_min as u32,
+        sec: tm.tm_sec as u32,
+        wday: usize::try_from(tm.tm_wday).ok()?.min(6),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_datetime() {
+        assert_eq!(format_datetime(0, Clock::Utc), "Thu Jan  1 00:00:00 1970");
+        assert_eq!(
+            format_datetime(1700000000, Clock::Utc),
+            "Tue Nov 14 22:13:20 2023"
+        );
+        assert_eq!(
+            format_datetime(-86401, Clock::Utc),
+            "Tue Dec 30 23:59:59 1969"
+        );
+        assert_eq!(
+            format_datetime(951782400, Clock::Utc),
+            "Tue Feb 29 00:00:00 2000"
+        );
+        assert_eq!(
+            format_datetime(MAX_CTIME + 1, Clock::Utc),
+            "*Invalid datetime*"
+        );
+        assert_eq!(
+            format_datetime(116444736000000000, Clock::Windows),
+            "Thu Jan  1 00:00:00 1970"
+        );
+        assert_eq!(
+            format_datetime(133444736000000000, Clock::Windows),
+            "Tue Nov 14 22:13:20 2023"
+        );
+    }
+
+    #[test]
+    fn check_dos_dates() {
+        // 2023-11-14 13:45:30
+        let date = (43 << 9) | (11 << 5) | 14;
+        let time = (13 << 11) | (45 << 5) | 15;
+        assert_eq!(format_dos_date(date), "Sun, Nov 14 2023");
+        assert_eq!(format_dos_time(time), "13:45:30");
+        assert_eq!(format_dos_date(0), "Sun, ? 00 1980");
+    }
+}
@@ -0,0 +1,283 @@
+//! A minimal reader for the ASN.1 DER tag-length-value encoding used by the
+//! `der` type, following libmagic's der.c.
+
+/// The names `der` tests use for the universal tags, indexed by tag number.
+const TAG_NAMES: [&str; 37] = [
+    "eoc",
+    "bool",
+    "int",
+    "bit_str",
+    "octet_str",
+    "null",
+    "obj_id",
+    "obj_desc",
+    "ext",
+    "real",
+    "enum",
+    "embed",
+    "utf8_str",
+    "rel_oid",
+    "time",
+    "res2",
+    "seq",
+    "set",
+    "num_str",
+    "prt_str",
+    "t61_str",
+    "vid_str",
+    "ia5_str",
+    "utc_time",
+    "gen_time",
+    "gr_str",
+    "vis_str",
+    "gen_str",
+    "univ_str",
+    "char_str",
+    "bmp_str",
+    "date",
+    "tod",
+    "datetime",
+    "duration",
+    "oid-iri",
+    "rel-oid-iri",
+];
+
+const TAG_UTF8_STRING: u32 = 0x0c;
+const TAG_PRINTABLE_STRING: u32 = 0x13;
+const TAG_IA5_STRING: u32 = 0x16;
+const TAG_UTC_TIME: u32 = 0x17;
+
+/// The size of the buffer libmagic renders tag names and values into.
+const BUF_SIZE: usize = 128;
+
+/// The identifier and length octets at the start of a DER encoded value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Header {
+    /// The tag number, without the class and constructed bits.
+    pub tag: u32,
+    /// The number of bytes taken up by the identifier and length octets.
+    pub size: usize,
+    /// The length of the contents.
+    pub len: usize,
+}
+
+impl Header {
+    /// Read the header at the start of `data`, returning `None` if it's
+    /// truncated or the contents wouldn't fit in `data`.
+    pub(crate) fn read(data: &[u8]) -> Option<Self> {
+        let mut pos = 0;
+        let tag = read_tag(data, &mut pos)?;
+        let len = read_length(data, &mut pos)?;
+        Some(Header {
+            tag,
+            size: pos,
+            len,
+        })
+    }
+}
+
+/// Read the tag number the way libmagic's `gettag` does. High tag numbers
+/// are accumulated on top of the 0x1f marker rather than from zero, and the
+/// final byte of the number, the one without the top bit set, is neither
+/// added nor consumed.
+fn read_tag(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let mut tag = (*data.get(*pos)? & 0x1f) as u32;
+    *pos += 1;
+    if tag != 0x1f {
+        return Some(tag);
+    }
+
+    loop {
+        let b = *data.get(*pos)?;
+        if b < 0x80 {
+            return Some(tag);
+        }
+        tag = tag.wrapping_mul(128).wrapping_add((b - 0x80) as u32);
+        *pos += 1;
+    }
+}
+
+fn read_length(data: &[u8], pos: &mut usize) -> Option<usize> {
+    let first = *data.get(*pos)?;
+    *pos += 1;
+    let digits = (first & 0x7f) as usize;
+
+    // Like libmagic we insist on at least one byte following the length
+    // octets of the long form, or the contents for the short form.
+    if *pos + digits >= data.len() {
+        return None;
+    }
+    if first & 0x80 == 0 {
+        return Some(digits);
+    }
+
+    let mut len: usize = 0;
+    for _ in 0..digits {
+        len = len.checked_mul(256)? | data[*pos] as usize;
+        *pos += 1;
+    }
+    if len > u32::MAX as usize - *pos || *pos + len > data.len() {
+        return None;
+    }
+    Some(len)
+}
+
+fn tag_name(tag: u32) -> String {
+    match TAG_NAMES.get(tag as usize) {
+        Some(name) => name.to_string(),
+        None => format!("{:#x}", tag),
+    }
+}
+
+/// Render the contents of a value the way libmagic's `der_data` does:
+/// strings as is, UTC times as a date and anything else as hex.
+fn render(tag: u32, contents: &[u8]) -> String {
+    match tag {
+        TAG_PRINTABLE_STRING | TAG_UTF8_STRING | TAG_IA5_STRING => {
+            let end = contents
+                .iter()
+                .position(|b| *b == 0)
+                .unwrap_or(contents.len())
+                .min(BUF_SIZE - 1);
+            return String::from_utf8_lossy(&contents[..end]).into_owned();
+        }
+        TAG_UTC_TIME if contents.len() >= 12 => {
+            let d: Vec<char> =
+                contents[..12].iter().map(|b| *b as char).collect();
+            return format!(
+                "20{}{}-{}{}-{}{} {}{}:{}{}:{}{} GMT",
+                d[0],
+                d[1],
+                d[2],
+                d[3],
+                d[4],
+                d[5],
+                d[6],
+                d[7],
+                d[8],
+                d[9],
+                d[10],
+                d[11]
+            );
+        }
+        _ => (),
+    }
+
+    contents
+        .iter()
+        .take((BUF_SIZE - 1) / 2)
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Check the DER value at the start of `data` against the value of a `der`
+/// test, e.g., `seq`, `int1` or `obj_id9=2a864886f70d010107`. That is a tag
+/// name, optionally followed by the expected length, and optionally by `=`
+/// and the expected contents, where `x` matches anything. As in libmagic an
+/// `x` anywhere else doesn't match.
+///
+/// Returns `None` if the value doesn't match and otherwise the rendered
+/// contents if they were compared, or an empty string if they weren't.
+pub(crate) fn compare(data: &[u8], expected: &[u8]) -> Option<String> {
+    let header = Header::read(data)?;
+    let name = tag_name(header.tag);
+    let mut rest = expected.strip_prefix(name.as_bytes())?;
+
+    loop {
+        match rest.first() {
+            None => return Some(String::new()),
+            Some(b'=') => break,
+            Some(b) if b.is_ascii_digit() => {
+                let digits =
+                    rest.iter().take_while(|b| b.is_ascii_digit()).count();
+                let len = rest[..digits].iter().fold(0usize, |len, b| {
+                    len.wrapping_mul(10).wrapping_add((b - b'0') as usize)
+                });
+                if len != header.len {
+                    return None;
+                }
+                rest = &rest[digits..];
+            }
+            Some(_) => return None,
+        }
+    }
+
+    let contents = &data[header.size..header.size + header.len];
+    let rendered = render(header.tag, contents);
+    let value = &rest[1..];
+    if rendered.as_bytes() != value && value != b"x" {
+        return None;
+    }
+    Some(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_headers() {
+        let header = Header::read(&[0x30, 0x03, 0x02, 0x01, 0x00, 0x00]);
+        assert_eq!(
+            header,
+            Some(Header {
+                tag: 0x10,
+                size: 2,
+                len: 3
+            })
+        );
+
+        // Long form length
+        let mut data = vec![0x30, 0x82, 0x01, 0x00];
+        data.resize(3 + 0x100, 0);
+        assert_eq!(Header::read(&data), None);
+        data.push(0);
+        assert_eq!(
+            Header::read(&data),
+            Some(Header {
+                tag: 0x10,
+                size: 4,
+                len: 0x100
+            })
+        );
+
+        // A high tag number starts from 0x1f and its last byte is read as
+        // the length.
+        let header = Header::read(&[0x9f, 0x81, 0x82, 0x01, 0xaa, 0x00]);
+        assert_eq!(
+            header,
+            Some(Header {
+                tag: (0x1f * 128 + 1) * 128 + 2,
+                size: 4,
+                len: 1
+            })
+        );
+
+        // The contents must fit.
+        assert_eq!(Header::read(&[0x30, 0x82, 0x01, 0x00, 0x00]), None);
+        assert_eq!(Header::read(&[0x30, 0x01, 0x00]), None);
+        assert_eq!(Header::read(&[0x30]), None);
+    }
+
+    #[test]
+    fn check_compare() {
+        let int = [0x02, 0x01, 0x05, 0x00];
+        assert_eq!(compare(&int, b"int"), Some(String::new()));
+        assert_eq!(compare(&int, b"int1"), Some(String::new()));
+        assert_eq!(compare(&int, b"int2"), None);
+        assert_eq!(compare(&int, b"int1=05").as_deref(), Some("05"));
+        assert_eq!(compare(&int, b"int=x").as_deref(), Some("05"));
+        assert_eq!(compare(&int, b"intx"), None);
+        assert_eq!(compare(&int, b"int=06"), None);
+        assert_eq!(compare(&int, b"seq"), None);
+
+        let name = b"\x13\x04Test\x00";
+        assert_eq!(compare(name, b"prt_str=x").as_deref(), Some("Test"));
+
+        let time = b"\x17\x0d231114221320Z\x00";
+        assert_eq!(
+            compare(time, b"utc_time=x").as_deref(),
+            Some("2023-11-14 22:13:20 GMT")
+        );
+    }
+}
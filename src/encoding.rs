@@ -0,0 +1,204 @@
+/// Character classes from libmagic's encoding.c
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    /// Never appears in text
+    F,
+    /// Appears in plain ASCII text
+    T,
+    /// Appears in ISO-8859 text
+    I,
+    /// Appears in non-ISO extended ASCII (Mac, IBM PC)
+    X,
+}
+
+fn char_class(byte: u8) -> CharClass {
+    use CharClass::*;
+    match byte {
+        // BEL, BS, HT, LF, VT, FF, CR, ESC
+        0x07..=0x0d | 0x1b => T,
+        0x00..=0x1f | 0x7f => F,
+        0x20..=0x7e => T,
+        // NEL
+        0x85 => T,
+        0x80..=0x9f => X,
+        0xa0..=0xff => I,
+    }
+}
+
+/// The text encoding of a buffer as guessed by libmagic's `file_encoding`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Ascii,
+    Utf8Bom,
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    Extended,
+    Binary,
+}
+
+impl Encoding {
+    pub(crate) fn detect(buf: &[u8]) -> Self {
+        if buf.iter().all(|b| char_class(*b) == CharClass::T) {
+            Encoding::Ascii
+        } else if buf.starts_with(&[0xef, 0xbb, 0xbf])
+            && looks_utf8(&buf[3..]).is_some()
+        {
+            Encoding::Utf8Bom
+        } else if looks_utf8(buf) == Some(true) {
+            Encoding::Utf8
+        } else if let Some(encoding) = looks_utf16(buf) {
+            encoding
+        } else if buf
+            .iter()
+            .all(|b| matches!(char_class(*b), CharClass::T | CharClass::I))
+        {
+            Encoding::Latin1
+        } else if buf.iter().all(|b| char_class(*b) != CharClass::F) {
+            Encoding::Extended
+        } else {
+            Encoding::Binary
+        }
+    }
+
+    pub(crate) fn is_text(&self) -> bool {
+        !matches!(self, Encoding::Binary)
+    }
+
+    /// The name libmagic uses for this encoding in descriptions.
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            Encoding::Ascii => "ASCII",
+            Encoding::Utf8Bom => "Unicode text, UTF-8 (with BOM)",
+            Encoding::Utf8 => "Unicode text, UTF-8",
+            Encoding::Utf16Le => "Unicode text, UTF-16, little-endian",
+            Encoding::Utf16Be => "Unicode text, UTF-16, big-endian",
+            Encoding::Latin1 => "ISO-8859",
+            Encoding::Extended => "Non-ISO extended-ASCII",
+            Encoding::Binary => "binary",
+        }
+    }
+
+    /// The charset reported for this encoding in MIME output.
+    pub(crate) fn mime(&self) -> &'static str {
+        match self {
+            Encoding::Ascii => "us-ascii",
+            Encoding::Utf8Bom | Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "iso-8859-1",
+            Encoding::Extended => "unknown-8bit",
+            Encoding::Binary => "binary",
+        }
+    }
+
+    /// Decode `buf` into code points so that line statistics can be gathered
+    /// independently of the encoding.
+    pub(crate) fn decode(&self, buf: &[u8]) -> Vec<u32> {
+        match self {
+            Encoding::Utf8Bom => decode_utf8(&buf[3..]),
+            Encoding::Utf8 => decode_utf8(buf),
+            Encoding::Utf16Le => buf[2..]
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]) as u32)
+                .collect(),
+            Encoding::Utf16Be => buf[2..]
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]) as u32)
+                .collect(),
+            _ => buf.iter().map(|b| *b as u32).collect(),
+        }
+    }
+}
+
+/// Check whether `buf` is valid UTF-8 text. Returns `Some(true)` if at least
+/// one multi-byte sequence was found, `Some(false)` if the text is plain
+/// ASCII, and `None` if it isn't UTF-8 text at all.
+fn looks_utf8(buf: &[u8]) -> Option<bool> {
+    let mut got_one = false;
+    let mut idx = 0;
+    while idx < buf.len() {
+        let byte = buf[idx];
+        if byte & 0x80 == 0 {
+            // Even if the whole file is valid UTF-8 sequences, still reject
+            // it if it uses weird control characters.
+            if char_class(byte) != CharClass::T {
+                return None;
+            }
+            idx += 1;
+            continue;
+        }
+
+        let following = match byte {
+            0xc0..=0xdf => 1,
+            0xe0..=0xef => 2,
+            0xf0..=0xf7 => 3,
+            0xf8..=0xfb => 4,
+            0xfc..=0xfd => 5,
+            _ => return None,
+        };
+
+        for _ in 0..following {
+            idx += 1;
+            if idx >= buf.len() {
+                // A sequence truncated at the end of the buffer is fine
+                return Some(got_one);
+            }
+            if buf[idx] & 0xc0 != 0x80 {
+                return None;
+            }
+        }
+        got_one = true;
+        idx += 1;
+    }
+    Some(got_one)
+}
+
+fn decode_utf8(buf: &[u8]) -> Vec<u32> {
+    String::from_utf8_lossy(buf)
+        .chars()
+        .map(|c| c as u32)
+        .collect()
+}
+
+/// libmagic only recognizes UTF-16 text that starts with a byte order mark.
+fn looks_utf16(buf: &[u8]) -> Option<Encoding> {
+    if buf.len() < 2 {
+        return None;
+    }
+
+    let encoding = match (buf[0], buf[1]) {
+        (0xff, 0xfe) => Encoding::Utf16Le,
+        (0xfe, 0xff) => Encoding::Utf16Be,
+        _ => return None,
+    };
+
+    for unit in encoding.decode(buf) {
+        if unit == 0xfffe {
+            return None;
+        }
+        if unit < 128 && char_class(unit as u8) != CharClass::T {
+            return None;
+        }
+    }
+
+    Some(encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_detection() {
+        assert_eq!(Encoding::detect(b"hello\n"), Encoding::Ascii);
+        assert_eq!(Encoding::detect("h\u{e9}llo\n".as_bytes()), Encoding::Utf8);
+        assert_eq!(Encoding::detect(b"\xef\xbb\xbfhello\n"), Encoding::Utf8Bom);
+        assert_eq!(Encoding::detect(b"\xff\xfeh\0i\0"), Encoding::Utf16Le);
+        assert_eq!(Encoding::detect(b"\xfe\xff\0h\0i"), Encoding::Utf16Be);
+        assert_eq!(Encoding::detect(b"h\xe9llo\n"), Encoding::Latin1);
+        assert_eq!(Encoding::detect(b"h\x90llo\n"), Encoding::Extended);
+        assert_eq!(Encoding::detect(b"\x00\x01\x02"), Encoding::Binary);
+    }
+}
@@ -0,0 +1,342 @@
+/// A value to be substituted into a description.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Arg<'a> {
+    /// An integer along with the bit width of the C type it is passed as
+    /// after the default argument promotions (i.e., 32 or 64).
+    Int(u64, u32),
+    Float(f64),
+    Str(&'a str),
+}
+
+#[derive(Debug, Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    width: usize,
+    precision: Option<usize>,
+    conversion: char,
+}
+
+/// Render a description from the magic database which, like libmagic, we
+/// treat as a printf(3) format string. Only the first conversion consumes
+/// `arg`. Any further conversions are copied through untouched as libmagic
+/// refuses to format descriptions that consume more than one argument.
+pub(crate) fn format(desc: &str, arg: Arg) -> String {
+    let mut out = String::with_capacity(desc.len());
+    let mut chars = desc.chars().peekable();
+    let mut consumed = false;
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            out.push('%');
+            continue;
+        }
+
+        let mut raw = String::from("%");
+        let mut spec = Spec::default();
+
+        while let Some(&c) = chars.peek() {
+            match c {
+                '-' => spec.left = true,
+                '0' => spec.zero = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alt = true,
+                _ => break,
+            }
+            raw.push(c);
+            chars.next();
+        }
+
+        while let Some(&c) = chars.peek() {
+            let Some(digit) = c.to_digit(10) else {
+                break;
+            };
+            spec.width = spec.width * 10 + digit as usize;
+            raw.push(c);
+            chars.next();
+        }
+
+        if chars.peek() == Some(&'.') {
+            raw.push('.');
+            chars.next();
+            let mut precision = 0;
+            while let Some(&c) = chars.peek() {
+                let Some(digit) = c.to_digit(10) else {
+                    break;
+                };
+                precision = precision * 10 + digit as usize;
+                raw.push(c);
+                chars.next();
+            }
+            spec.precision = Some(precision);
+        }
+
+        // Length modifiers carry no information for us as the width of the
+        // argument is known from the value type.
+        while let Some(&c) = chars.peek() {
+            if !matches!(c, 'h' | 'l' | 'q' | 'j' | 'z' | 't' | 'L') {
+                break;
+            }
+            raw.push(c);
+            chars.next();
+        }
+
+        let Some(conversion) = chars.next() else {
+            out.push_str(&raw);
+            break;
+        };
+        raw.push(conversion);
+
+        if consumed || !"diouxXcseEfFgG".contains(conversion) {
+            out.push_str(&raw);
+            continue;
+        }
+
+        spec.conversion = conversion;
+        out.push_str(&format_spec(&spec, arg));
+        consumed = true;
+    }
+
+    out
+}
+
+fn format_spec(spec: &Spec, arg: Arg) -> String {
+    match spec.conversion {
+        'd' | 'i' | 'o' | 'u' | 'x' | 'X' => format_int(spec, arg),
+        'c' => {
+            let c = match arg {
+                Arg::Int(value, _) => char::from(value as u8),
+                Arg::Float(value) => char::from(value as u8),
+                Arg::Str(s) => s.chars().next().unwrap_or('\0'),
+            };
+            pad(spec, String::new(), c.to_string(), false)
+        }
+        's' => {
+            let s = match arg {
+                Arg::Int(value, bits) => truncate(value, bits).to_string(),
+                Arg::Float(value) => format_general(value, 6, false),
+                Arg::Str(s) => s.to_string(),
+            };
+            let s = match spec.precision {
+                Some(precision) => s.chars().take(precision).collect(),
+                None => s,
+            };
+            pad(spec, String::new(), s, false)
+        }
+        _ => format_float(spec, arg),
+    }
+}
+
+fn truncate(value: u64, bits: u32) -> u64 {
+    if bits >= 64 {
+        value
+    } else {
+        value & ((1u64 << bits) - 1)
+    }
+}
+
+fn format_int(spec: &Spec, arg: Arg) -> String {
+    let (value, bits) = match arg {
+        Arg::Int(value, bits) => (value, bits),
+        Arg::Float(value) => (value as i64 as u64, 64),
+        Arg::Str(s) => (s.parse::<u64>().unwrap_or(0), 64),
+    };
+    let value = truncate(value, bits);
+
+    let mut sign = String::new();
+    let digits = match spec.conversion {
+        'd' | 'i' => {
+            let signed = if bits >= 64 {
+                value as i64
+            } else {
+                // Sign extend from the argument width.
+                let shift = 64 - bits;
+                ((value << shift) as i64) >> shift
+            };
+            if signed < 0 {
+                sign.push('-');
+            } else if spec.plus {
+                sign.push('+');
+            } else if spec.space {
+                sign.push(' ');
+            }
+            signed.unsigned_abs().to_string()
+        }
+        'o' => format!("{:o}", value),
+        'u' => value.to_string(),
+        'x' => format!("{:x}", value),
+        'X' => format!("{:X}", value),
+        _ => unreachable!("invalid integer conversion"),
+    };
+
+    let mut digits = match spec.precision {
+        Some(0) if value == 0 => String::new(),
+        Some(precision) if digits.len() < precision => {
+            "0".repeat(precision - digits.len()) + &digits
+        }
+        _ => digits,
+    };
+
+    if spec.alt {
+        match spec.conversion {
+            'o' if !digits.starts_with('0') => digits.insert(0, '0'),
+            'x' if value != 0 => sign.push_str("0x"),
+            'X' if value != 0 => sign.push_str("0X"),
+            _ => (),
+        }
+    }
+
+    pad(spec, sign, digits, spec.precision.is_none())
+}
+
+fn format_float(spec: &Spec, arg: Arg) -> String {
+    let value = match arg {
+        Arg::Int(value, bits) => truncate(value, bits) as f64,
+        Arg::Float(value) => value,
+        Arg::Str(s) => s.parse::<f64>().unwrap_or(0.0),
+    };
+
+    let mut sign = String::new();
+    if value.is_sign_negative() && !value.is_nan() {
+        sign.push('-');
+    } else if spec.plus {
+        sign.push('+');
+    } else if spec.space {
+        sign.push(' ');
+    }
+    let value = value.abs();
+
+    let upper = spec.conversion.is_ascii_uppercase();
+    let body = if value.is_nan() {
+        "nan".to_string()
+    } else if value.is_infinite() {
+        "inf".to_string()
+    } else {
+        let precision = spec.precision.unwrap_or(6);
+        match spec.conversion.to_ascii_lowercase() {
+            'e' => format_exponent(value, precision),
+            'f' => format!("{:.*}", precision, value),
+            _ => format_general(value, precision, spec.alt),
+        }
+    };
+    let body = if upper { body.to_uppercase() } else { body };
+
+    pad(spec, sign, body, value.is_finite())
+}
+
+/// Format a non-negative value like `%.Ne` does in C.
+fn format_exponent(value: f64, precision: usize) -> String {
+    let formatted = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = formatted
+        .split_once('e')
+        .expect("exponent formatting always contains an 'e'");
+    let exponent: i32 = exponent.parse().expect("invalid exponent");
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+/// Format a non-negative value like `%.Ng` does in C.
+fn format_general(value: f64, precision: usize, alt: bool) -> String {
+    let precision = precision.max(1);
+    let exponent = if value == 0.0 {
+        0
+    } else {
+        // The exponent has to be determined after rounding to the requested
+        // number of significant digits.
+        let formatted = format!("{:.*e}", precision - 1, value);
+        let (_, exponent) = formatted
+            .split_once('e')
+            .expect("exponent formatting always contains an 'e'");
+        exponent.parse::<i32>().expect("invalid exponent")
+    };
+
+    let mut formatted = if exponent < -4 || exponent >= precision as i32 {
+        format_exponent(value, precision - 1)
+    } else {
+        let decimals = (precision as i32 - 1 - exponent) as usize;
+        format!("{:.*}", decimals, value)
+    };
+
+    if !alt {
+        let (mantissa, suffix) = match formatted.find('e') {
+            Some(idx) => formatted.split_at(idx),
+            None => (formatted.as_str(), ""),
+        };
+        if mantissa.contains('.') {
+            let mantissa = mantissa.trim_end_matches('0').trim_end_matches('.');
+            formatted = format!("{}{}", mantissa, suffix);
+        }
+    }
+
+    formatted
+}
+
+fn pad(spec: &Spec, prefix: String, body: String, zero_ok: bool) -> String {
+    let len = prefix.chars().count() + body.chars().count();
+    if len >= spec.width {
+        return prefix + &body;
+    }
+
+    let fill = spec.width - len;
+    if spec.left {
+        prefix + &body + &" ".repeat(fill)
+    } else if spec.zero && zero_ok {
+        prefix + &"0".repeat(fill) + &body
+    } else {
+        " ".repeat(fill) + &prefix + &body
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_integer_formatting() {
+        assert_eq!(format("version %d", Arg::Int(3, 32)), "version 3");
+        assert_eq!(format("%d", Arg::Int(0xffffffff, 32)), "-1");
+        assert_eq!(format("%u", Arg::Int(0xffffffff, 32)), "4294967295");
+        assert_eq!(format("%#x", Arg::Int(0x1f, 32)), "0x1f");
+        assert_eq!(format("%#x", Arg::Int(0, 32)), "0");
+        assert_eq!(format("%04X", Arg::Int(0xab, 32)), "00AB");
+        assert_eq!(format("%#o", Arg::Int(8, 32)), "010");
+        assert_eq!(format("%-4d|", Arg::Int(7, 32)), "7   |");
+        assert_eq!(format("%.3d", Arg::Int(7, 32)), "007");
+        assert_eq!(format("%lld", Arg::Int(u64::MAX, 64)), "-1");
+        assert_eq!(format("%c", Arg::Int(0x41, 32)), "A");
+        assert_eq!(format("%s", Arg::Int(0xffff, 32)), "65535");
+        assert_eq!(format("100%% %d", Arg::Int(1, 32)), "100% 1");
+        assert_eq!(format("%d %d", Arg::Int(1, 32)), "1 %d");
+    }
+
+    #[test]
+    fn check_string_formatting() {
+        assert_eq!(format("name: %s", Arg::Str("foo")), "name: foo");
+        assert_eq!(format("%.2s", Arg::Str("foo")), "fo");
+        assert_eq!(format("[%5s]", Arg::Str("foo")), "[  foo]");
+        assert_eq!(format("no conversion", Arg::Str("foo")), "no conversion");
+    }
+
+    #[test]
+    fn check_float_formatting() {
+        assert_eq!(format("%g", Arg::Float(1.5)), "1.5");
+        assert_eq!(format("%g", Arg::Float(100000.0)), "100000");
+        assert_eq!(format("%g", Arg::Float(1000000.0)), "1e+06");
+        assert_eq!(format("%g", Arg::Float(0.0001)), "0.0001");
+        assert_eq!(format("%g", Arg::Float(0.00001)), "1e-05");
+        assert_eq!(format("%g", Arg::Float(-2.0)), "-2");
+        assert_eq!(format("%.2f", Arg::Float(1.23456)), "1.23");
+        assert_eq!(format("%e", Arg::Float(1234.5)), "1.234500e+03");
+        assert_eq!(format("%g", Arg::Float(f64::INFINITY)), "inf");
+    }
+}
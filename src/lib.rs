@@ -1,5 +1,12 @@
+mod date;
+mod der;
+mod encoding;
+mod format;
 pub mod loader;
 mod magic;
+pub mod magic_set;
+mod posix;
+pub mod softmagic;
 pub mod structs;
 mod traits;
 mod value;
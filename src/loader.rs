@@ -16,8 +16,9 @@ const MAGIC_DATABASE_VERSION: u32 = 19;
 /// A `struct magic` from libmagic is 432 bytes
 pub(crate) const MAGIC_SIZE: usize = 432;
 
-/// The number of Magic sets. I have no idea why this is 2... yet.
-const MAGIC_SETS: usize = 2;
+/// The number of Magic sets. The first holds the regular entries and the
+/// second holds the `name` entries which are only run by `use` entries.
+pub(crate) const MAGIC_SETS: usize = 2;
 
 #[derive(Debug, Error)]
 pub enum LoaderError {
@@ -56,8 +57,7 @@ fn load_db_impl(bytes: Vec<u8>) -> Result<MagicMap> {
     }
 
     // N.B., the +1 here is because libmagic reserves the first "entry" to just
-    // be a few pieces of metadata. I have no idea yet why there are exaclt two
-    // magic sets.
+    // be a few pieces of metadata.
     if num_records < MAGIC_SETS + 1 {
         return Err(LoaderError::InvalidRecordCount(num_records));
     }
@@ -108,14 +108,10 @@ fn load_db_impl(bytes: Vec<u8>) -> Result<MagicMap> {
     let mut records = iter
         .map(Magic::from_bytes)
         .collect::<std::result::Result<Vec<_>, MagicError>>()?;
-    println!("LEFT: {} RIGHT: {}", left_num_records, right_num_records);
     let right_records = records.split_off(left_num_records as usize);
     records.shrink_to_fit();
 
-    Ok(MagicMap {
-        left: records,
-        right: right_records,
-    })
+    Ok(MagicMap::new(records, right_records))
 }
 
 #[cfg(test)]
@@ -126,11 +122,11 @@ mod tests {
     fn test_load_db() -> Result<()> {
         let map = load_db("data/magic.mgc")?;
 
-        for m in map.left {
+        for m in map.left() {
             println!("< {:#?}", m);
         }
 
-        for m in map.right {
+        for m in map.right() {
             println!("> {:#?}", m);
         }
 
@@ -3,7 +3,9 @@ use std::fmt;
 use thiserror::Error;
 
 use crate::traits::ReadLittleEndian;
-use crate::value::{Value, ValueError, ValueOption, ValueType};
+use crate::value::{
+    NumericValue, StringFlags, Value, ValueError, ValueOption, ValueType,
+};
 
 #[derive(Debug, Error)]
 pub enum MagicError {
@@ -25,6 +27,9 @@ pub enum MagicError {
 
 type Result<T> = std::result::Result<T, MagicError>;
 
+/// The unit libmagic scales strengths by.
+const MULT: isize = 10;
+
 // Total size: 434
 #[derive(Debug)]
 pub struct Magic {
@@ -62,6 +67,91 @@ pub struct Magic {
 }
 
 impl Magic {
+    /// The value of a numeric test, taking the `UNSIGNED` flag into
+    /// account.
+    pub fn numeric_value(&self) -> Option<NumericValue> {
+        self.value.numeric(self.flags.is_unsigned())
+    }
+
+    /// The modifiers of a string type test, or no modifiers at all for any
+    /// other test.
+    pub fn string_flags(&self) -> StringFlags {
+        match self.value_options {
+            ValueOption::String { flags, .. } => flags,
+            ValueOption::Numeric { .. } => StringFlags::default(),
+        }
+    }
+
+    /// The range of a `search` or `regex` test, or zero for any other test.
+    pub fn string_range(&self) -> u32 {
+        match self.value_options {
+            ValueOption::String { count, .. } => count,
+            ValueOption::Numeric { .. } => 0,
+        }
+    }
+
+    /// How specific this entry is, which libmagic orders the top level
+    /// entries by so that the most specific match is found first. This is
+    /// the same as libmagic's `apprentice_magic_strength`.
+    pub fn strength(&self) -> usize {
+        use ValueType::*;
+
+        let vallen = self.value_len as isize;
+        let mut val = 2 * MULT;
+        match self.value_type {
+            // Make sure this sorts last
+            Default => return 0,
+            String | PString | Octal => val += vallen * MULT,
+            BeString16 | LeString16 => val += vallen * MULT / 2,
+            Search => {
+                if vallen > 0 {
+                    val += vallen * (MULT / vallen).max(1);
+                }
+            }
+            Regex => {
+                let count = nonmagic(self.value.string()) as isize;
+                val += count * (MULT / count).max(1);
+            }
+            Indirect | Name | Use | Clear | Invalid => (),
+            Der => val += MULT,
+            Guid => val += 16 * MULT,
+            vtype => val += vtype.size().unwrap_or(0) as isize * MULT,
+        }
+
+        match self.relation {
+            // Matches (almost) anything so penalize
+            Relation::Anything | Relation::NotEqual => val = 0,
+            // Exact match, prefer
+            Relation::Equal => val += MULT,
+            // Comparison match reduce strength
+            Relation::Lesser | Relation::Greater => val -= 2 * MULT,
+            // Masking bits
+            Relation::BitXor | Relation::BitAnd => val -= MULT,
+        }
+
+        let factor = self.factor as isize;
+        match self.factor_operation {
+            FactorOperation::None => (),
+            FactorOperation::Add => val += factor,
+            FactorOperation::Subtract => val -= factor,
+            FactorOperation::Multiply => val *= factor,
+            FactorOperation::Divide => {
+                val = val.checked_div(factor).unwrap_or(val)
+            }
+        }
+
+        // Only `default` entries have a strength of zero.
+        let mut strength = val.max(1) as usize;
+
+        // Entries with no description get a bonus because they depend on
+        // their continuations to print something.
+        if self.desc.is_empty() {
+            strength += 1;
+        }
+
+        strength
+    }
+
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
         if bytes.len() != crate::loader::MAGIC_SIZE {
             return Err(MagicError::InvalidBufferLength(
@@ -88,9 +178,11 @@ impl Magic {
         let indirection_offset = i32::read_le(&bytes[16..20]);
         let line_number = u32::read_le(&bytes[20..24]);
 
-        let value_options = if value_len > 0 {
+        // The field is a union in libmagic, which holds the range and flags
+        // for string types and the mask for everything else.
+        let value_options = if value_type.is_string() {
             let count = u32::read_le(&bytes[24..28]);
-            let flags = u32::read_le(&bytes[28..32]);
+            let flags = StringFlags::from(u32::read_le(&bytes[28..32]));
             ValueOption::String { count, flags }
         } else {
             let mask = u64::read_le(&bytes[24..32]);
@@ -134,14 +226,14 @@ pub struct MagicFlags {
 }
 
 impl MagicFlags {
-    const INDIRECT: u8 = 0x01;
-    const OFFSET_ADD: u8 = 0x02;
-    const INDIRECT_OFFSET_ADD: u8 = 0x04;
-    const UNSIGNED: u8 = 0x08;
-    const NO_SPACE: u8 = 0x10;
-    const BIN_TEST: u8 = 0x20;
-    const TEXT_TEST: u8 = 0x40;
-    const OFFSET_NEGATIVE: u8 = 0x80;
+    pub(crate) const INDIRECT: u8 = 0x01;
+    pub(crate) const OFFSET_ADD: u8 = 0x02;
+    pub(crate) const INDIRECT_OFFSET_ADD: u8 = 0x04;
+    pub(crate) const UNSIGNED: u8 = 0x08;
+    pub(crate) const NO_SPACE: u8 = 0x10;
+    pub(crate) const BIN_TEST: u8 = 0x20;
+    pub(crate) const TEXT_TEST: u8 = 0x40;
+    pub(crate) const OFFSET_NEGATIVE: u8 = 0x80;
 
     pub fn is_indirect(&self) -> bool {
         self.is_set(Self::INDIRECT)
@@ -269,7 +361,6 @@ pub enum FactorOperation {
     Subtract,
     Multiply,
     Divide,
-    Modulo,
 }
 
 impl TryFrom<u8> for FactorOperation {
@@ -394,7 +485,154 @@ impl TryFrom<u8> for ConditionalType {
     }
 }
 
+/// Count the characters of a regular expression that match literally, which
+/// is how libmagic measures the specificity of a regex test.
+fn nonmagic(pattern: &[u8]) -> usize {
+    let mut count = 0;
+    let mut idx = 0;
+    while idx < pattern.len() {
+        match pattern[idx] {
+            // Escaped anything counts 1
+            b'\\' => {
+                idx += 1;
+                count += 1;
+            }
+            // Magic characters count 0
+            b'?' | b'*' | b'.' | b'+' | b'^' | b'$' => (),
+            // Bracketed expressions count 1 for the ']'
+            b'[' => {
+                while idx < pattern.len() && pattern[idx] != b']' {
+                    idx += 1;
+                }
+                continue;
+            }
+            // Braced expressions count 0
+            b'{' => {
+                while idx < pattern.len() && pattern[idx] != b'}' {
+                    idx += 1;
+                }
+            }
+            // Anything else counts 1
+            _ => count += 1,
+        }
+        idx += 1;
+    }
+    count.max(1)
+}
+
 fn bytes_to_string(bytes: &[u8]) -> Result<String> {
     let first_null = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
     Ok(std::str::from_utf8(&bytes[0..first_null])?.to_string())
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Build an entry the way the compiler would for a test of `value_type`
+    /// at `offset`, where `value` holds the bytes of the value field.
+    /// Top level entries are binary tests.
+    pub(crate) fn entry(
+        cont_level: u16,
+        offset: i32,
+        value_type: ValueType,
+        relation: Relation,
+        value: &[u8],
+        desc: &str,
+    ) -> Magic {
+        let value_len = if value_type.is_string() {
+            value.len() as u8
+        } else {
+            0
+        };
+        let mut bytes = [0u8; 128];
+        bytes[..value.len()].copy_from_slice(value);
+        let flags = match cont_level {
+            0 => MagicFlags::BIN_TEST,
+            _ => 0,
+        };
+
+        Magic {
+            cont_level,
+            flags: MagicFlags::from(flags),
+            factor: 0,
+            relation,
+            value_len,
+            value_type,
+            indirection_type: ValueType::Invalid,
+            indirection_operation: IndirectionOperation::default(),
+            mask_operation: IndirectionOperation::default(),
+            conditional_type: ConditionalType::None,
+            factor_operation: FactorOperation::None,
+            offset,
+            indirection_offset: 0,
+            line_number: 0,
+            value_options: ValueOption::default(),
+            value: Value::new(value_type, value_len, &bytes).unwrap(),
+            desc: desc.to_string(),
+            mimetype: String::new(),
+            apple: String::new(),
+            ext: String::new(),
+        }
+    }
+
+    /// Build a top level string type entry like `entry` with the range and
+    /// modifiers of the test given by `count` and `flags`.
+    pub(crate) fn string_entry(
+        value_type: ValueType,
+        relation: Relation,
+        value: &[u8],
+        desc: &str,
+        count: u32,
+        flags: u32,
+    ) -> Magic {
+        let mut m = entry(0, 0, value_type, relation, value, desc);
+        m.value_options = ValueOption::String {
+            count,
+            flags: StringFlags::from(flags),
+        };
+        m
+    }
+
+    #[test]
+    fn check_strength() {
+        use Relation::*;
+        use ValueType::*;
+
+        assert_eq!(entry(0, 0, LeLong, Equal, &[1], "x").strength(), 70);
+        assert_eq!(entry(0, 0, Byte, Greater, &[1], "x").strength(), 10);
+        assert_eq!(entry(0, 0, BeShort, BitAnd, &[1], "x").strength(), 30);
+        assert_eq!(entry(0, 0, Byte, Anything, &[0], "x").strength(), 1);
+        assert_eq!(entry(0, 0, String, Equal, b"\x7fELF", "x").strength(), 70);
+        assert_eq!(entry(0, 0, LeString16, Equal, b"ab", "x").strength(), 40);
+        assert_eq!(entry(0, 0, Search, Equal, b"abc", "x").strength(), 39);
+        assert_eq!(
+            entry(0, 0, Regex, Equal, b"^a[bc]+\\.d{2}", "x").strength(),
+            38
+        );
+        assert_eq!(entry(0, 0, Default, Anything, &[0], "x").strength(), 0);
+
+        // No description
+        assert_eq!(entry(0, 0, Byte, Equal, &[1], "").strength(), 41);
+
+        let mut m = entry(0, 0, Byte, Equal, &[1], "x");
+        m.factor = 5;
+        m.factor_operation = FactorOperation::Multiply;
+        assert_eq!(m.strength(), 200);
+        m.factor_operation = FactorOperation::Subtract;
+        assert_eq!(m.strength(), 35);
+
+        // The factor is applied before the strength is clamped.
+        let mut m = entry(0, 0, Byte, Anything, &[0], "x");
+        m.factor = 5;
+        m.factor_operation = FactorOperation::Add;
+        assert_eq!(m.strength(), 5);
+        m.factor_operation = FactorOperation::Multiply;
+        assert_eq!(m.strength(), 1);
+
+        let mut m = entry(0, 0, Byte, Equal, &[1], "x");
+        m.factor = 50;
+        m.factor_operation = FactorOperation::Subtract;
+        assert_eq!(m.strength(), 1);
+    }
+}
@@ -0,0 +1,648 @@
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::encoding::Encoding;
+use crate::loader::{self, LoaderError};
+use crate::softmagic::{self, MatchError, TestType};
+use crate::structs::MagicMap;
+
+/// Lines longer than this are reported as "very long lines" in text
+/// descriptions.
+const MAX_LINE_LEN: usize = 300;
+
+#[derive(Debug, Error)]
+pub enum MagicSetError {
+    #[error("Error reading '{0}': {1}")]
+    Io(String, std::io::Error),
+    #[error("Error loading magic database: {0}")]
+    Loader(#[from] LoaderError),
+    #[error("Error matching magic: {0}")]
+    Match(#[from] MatchError),
+}
+
+type Result<T> = std::result::Result<T, MagicSetError>;
+
+/// Flags controlling how a `MagicSet` identifies data. The values match the
+/// `MAGIC_*` flags accepted by libmagic's `magic_open`.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct MagicSetFlags {
+    flags: u32,
+}
+
+impl MagicSetFlags {
+    pub const NONE: u32 = 0x0000000;
+    pub const MIME_TYPE: u32 = 0x0000010;
+    pub const CONTINUE: u32 = 0x0000020;
+    pub const RAW: u32 = 0x0000100;
+    pub const MIME_ENCODING: u32 = 0x0000400;
+    pub const MIME: u32 = Self::MIME_TYPE | Self::MIME_ENCODING;
+    pub const APPLE: u32 = 0x0000800;
+    pub const EXTENSION: u32 = 0x1000000;
+
+    pub fn is_mime_type(&self) -> bool {
+        self.is_set(Self::MIME_TYPE)
+    }
+
+    pub fn is_continue(&self) -> bool {
+        self.is_set(Self::CONTINUE)
+    }
+
+    pub fn is_raw(&self) -> bool {
+        self.is_set(Self::RAW)
+    }
+
+    pub fn is_mime_encoding(&self) -> bool {
+        self.is_set(Self::MIME_ENCODING)
+    }
+
+    pub fn is_apple(&self) -> bool {
+        self.is_set(Self::APPLE)
+    }
+
+    pub fn is_extension(&self) -> bool {
+        self.is_set(Self::EXTENSION)
+    }
+
+    /// Whether any of the MIME flags are set.
+    pub fn is_mime(&self) -> bool {
+        self.flags & Self::MIME != 0
+    }
+
+    /// Whether matched descriptions are reported rather than one of the
+    /// alternate output modes.
+    pub fn prints_description(&self) -> bool {
+        !self.is_mime() && !self.is_apple() && !self.is_extension()
+    }
+
+    pub fn is_set(&self, flag: u32) -> bool {
+        self.flags & flag == flag
+    }
+}
+
+impl From<u32> for MagicSetFlags {
+    fn from(value: u32) -> Self {
+        Self { flags: value }
+    }
+}
+
+impl fmt::Debug for MagicSetFlags {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut str_flags = Vec::with_capacity(6);
+
+        if self.is_mime_type() {
+            str_flags.push("MIME_TYPE");
+        }
+
+        if self.is_continue() {
+            str_flags.push("CONTINUE");
+        }
+
+        if self.is_raw() {
+            str_flags.push("RAW");
+        }
+
+        if self.is_mime_encoding() {
+            str_flags.push("MIME_ENCODING");
+        }
+
+        if self.is_apple() {
+            str_flags.push("APPLE");
+        }
+
+        if self.is_extension() {
+            str_flags.push("EXTENSION");
+        }
+
+        if str_flags.is_empty() {
+            str_flags.push("NONE");
+        }
+
+        fmt.write_fmt(format_args!("{}", str_flags.join(" | ")))
+    }
+}
+
+/// Limits bounding the amount of work done while identifying data. The
+/// defaults are the same as libmagic's, except for `search_max`, which
+/// libmagic doesn't have.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// Maximum recursion depth of `indirect` magic
+    pub indirection_max: u16,
+    /// Maximum number of nested `use` invocations
+    pub name_max: u16,
+    /// Maximum number of ELF section headers processed
+    pub elf_shnum_max: u16,
+    /// Maximum number of ELF program headers processed
+    pub elf_phnum_max: u16,
+    /// Maximum number of ELF notes processed
+    pub elf_notes_max: u16,
+    /// Maximum length of the data a regex is applied to
+    pub regex_max: u16,
+    /// Maximum number of offsets a `search` test tries the pattern at,
+    /// whatever the range of the test. This is not a libmagic limit; the
+    /// default is the same as `bytes_max` so that it never cuts a search
+    /// short in data read with the default limits.
+    pub search_max: usize,
+    /// Maximum number of bytes read from a file or descriptor
+    pub bytes_max: usize,
+    /// Maximum number of bytes scanned when guessing the text encoding
+    pub encoding_max: usize,
+    /// Maximum ELF section size processed
+    pub elf_shsize_max: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            indirection_max: 50,
+            name_max: 50,
+            elf_shnum_max: 32768,
+            elf_phnum_max: 2048,
+            elf_notes_max: 256,
+            regex_max: 8192,
+            search_max: 7 * 1024 * 1024,
+            bytes_max: 7 * 1024 * 1024,
+            encoding_max: 64 * 1024,
+            elf_shsize_max: 128 * 1024 * 1024,
+        }
+    }
+}
+
+/// The classic Mac OS creator and type codes associated with a file type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AppleType {
+    pub creator: String,
+    pub file_type: String,
+}
+
+impl AppleType {
+    /// The value reported by libmagic when no codes are known.
+    const UNKNOWN: &'static str = "UNKNUNKN";
+
+    /// Split the eight character creator/type string stored in the magic
+    /// database into its two four character codes.
+    fn parse(codes: &str) -> Option<Self> {
+        let codes: String = codes.chars().take(8).collect();
+        if codes.is_empty() || codes == Self::UNKNOWN {
+            return None;
+        }
+
+        let split = codes
+            .char_indices()
+            .nth(4)
+            .map(|(idx, _)| idx)
+            .unwrap_or(codes.len());
+        let (creator, file_type) = codes.split_at(split);
+        Some(AppleType {
+            creator: creator.to_string(),
+            file_type: file_type.to_string(),
+        })
+    }
+}
+
+/// A loaded magic database along with the flags and limits used to identify
+/// data with it. This is the equivalent of libmagic's `magic_t`.
+pub struct MagicSet {
+    map: MagicMap,
+    flags: MagicSetFlags,
+    limits: Limits,
+}
+
+impl MagicSet {
+    pub fn new(map: MagicMap) -> Self {
+        MagicSet {
+            map,
+            flags: MagicSetFlags::default(),
+            limits: Limits::default(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(loader::load_db(path)?))
+    }
+
+    pub fn map(&self) -> &MagicMap {
+        &self.map
+    }
+
+    pub fn flags(&self) -> MagicSetFlags {
+        self.flags
+    }
+
+    pub fn set_flags<F: Into<MagicSetFlags>>(&mut self, flags: F) {
+        self.flags = flags.into();
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Identify the contents of `buf`.
+    pub fn buffer(&self, buf: &[u8]) -> Result<String> {
+        let flags = self.flags;
+        let (mut desc, encoding) = self.identify(buf, flags)?;
+
+        if flags.is_mime_encoding() {
+            if flags.is_mime_type() {
+                desc.push_str("; charset=");
+            }
+            desc.push_str(encoding.mime());
+        }
+
+        Ok(desc)
+    }
+
+    /// List the file extensions commonly used for the contents of `buf`. An
+    /// empty list means no extensions are known for the matched type.
+    pub fn extensions(&self, buf: &[u8]) -> Result<Vec<String>> {
+        let flags = MagicSetFlags::from(MagicSetFlags::EXTENSION);
+        let (desc, _) = self.identify(buf, flags)?;
+        let extensions = desc
+            .split('/')
+            .filter(|ext| !ext.is_empty() && *ext != "???")
+            .map(|ext| ext.to_string())
+            .collect();
+        Ok(extensions)
+    }
+
+    /// Look up the Apple creator and type codes for the contents of `buf`.
+    pub fn apple(&self, buf: &[u8]) -> Result<Option<AppleType>> {
+        let flags = MagicSetFlags::from(MagicSetFlags::APPLE);
+        let (desc, _) = self.identify(buf, flags)?;
+        Ok(AppleType::parse(&desc))
+    }
+
+    /// Identify the contents of the file at `path`, reading at most
+    /// `Limits::bytes_max` bytes.
+    pub fn file<P: AsRef<Path>>(&self, path: P) -> Result<String> {
+        let file = std::fs::File::open(&path).map_err(|e| {
+            MagicSetError::Io(path.as_ref().display().to_string(), e)
+        })?;
+        self.read(file, &path.as_ref().display().to_string())
+    }
+
+    /// Identify the data read from `reader`, reading at most
+    /// `Limits::bytes_max` bytes.
+    pub fn descriptor<R: Read>(&self, reader: R) -> Result<String> {
+        self.read(reader, "descriptor")
+    }
+
+    fn read<R: Read>(&self, reader: R, name: &str) -> Result<String> {
+        let mut buf = Vec::new();
+        reader
+            .take(self.limits.bytes_max as u64)
+            .read_to_end(&mut buf)
+            .map_err(|e| MagicSetError::Io(name.to_string(), e))?;
+        self.buffer(&buf)
+    }
+
+    fn identify(
+        &self,
+        buf: &[u8],
+        flags: MagicSetFlags,
+    ) -> Result<(String, Encoding)> {
+        match buf.len() {
+            0 => {
+                let desc = fallback(flags, buf, "empty");
+                return Ok((desc, Encoding::Binary));
+            }
+            1 => {
+                let desc = fallback(flags, buf, "very short file (no magic)");
+                return Ok((desc, Encoding::Binary));
+            }
+            _ => (),
+        }
+
+        let sample = &buf[..buf.len().min(self.limits.encoding_max)];
+        let encoding = Encoding::detect(sample);
+
+        let matched = softmagic::softmagic(self, flags, buf, TestType::Binary)?;
+        if let Some(desc) = matched {
+            return Ok((desc, encoding));
+        }
+
+        if encoding.is_text() {
+            let desc = self.text(flags, buf, sample, encoding)?;
+            return Ok((desc, encoding));
+        }
+
+        Ok((fallback(flags, buf, "data"), encoding))
+    }
+
+    /// Describe a buffer that looks like text. This is a port of the
+    /// description half of libmagic's ascmagic.c.
+    fn text(
+        &self,
+        flags: MagicSetFlags,
+        buf: &[u8],
+        sample: &[u8],
+        encoding: Encoding,
+    ) -> Result<String> {
+        let matched = softmagic::softmagic(self, flags, buf, TestType::Text)?;
+
+        if !flags.prints_description() {
+            let desc = match matched {
+                Some(desc) => desc,
+                None if flags.is_mime_type() => "text/plain".to_string(),
+                None => fallback(flags, buf, ""),
+            };
+            return Ok(desc);
+        }
+
+        let mut desc = match matched {
+            Some(desc) => desc + ", ",
+            None => String::new(),
+        };
+
+        desc.push_str(encoding.code());
+        desc.push_str(" text");
+
+        if sample.starts_with(b"#!") {
+            desc.push_str(" executable");
+        }
+
+        let stats = LineStats::new(&encoding.decode(sample));
+        if stats.max_line_len > 0 {
+            desc.push_str(&format!(
+                ", with very long lines ({})",
+                stats.max_line_len
+            ));
+        }
+
+        if stats.lf == 0 && stats.cr == 0 && stats.crlf == 0 && stats.nel == 0 {
+            desc.push_str(", with no line terminators");
+        } else if stats.crlf != 0 || stats.cr != 0 || stats.nel != 0 {
+            let terminators = [
+                ("CRLF", stats.crlf),
+                ("CR", stats.cr),
+                ("LF", stats.lf),
+                ("NEL", stats.nel),
+            ]
+            .iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+            desc.push_str(&format!(
+                ", with {} line terminators",
+                terminators.join(", ")
+            ));
+        }
+
+        if stats.has_escapes {
+            desc.push_str(", with escape sequences");
+        }
+
+        if stats.has_backspace {
+            desc.push_str(", with overstriking");
+        }
+
+        Ok(desc)
+    }
+}
+
+/// The result reported when nothing more specific could be found.
+fn fallback(flags: MagicSetFlags, buf: &[u8], def: &str) -> String {
+    if flags.is_mime_type() {
+        if buf.is_empty() {
+            "application/x-empty".to_string()
+        } else {
+            "application/octet-stream".to_string()
+        }
+    } else if flags.is_mime() {
+        String::new()
+    } else if flags.is_apple() {
+        AppleType::UNKNOWN.to_string()
+    } else if flags.is_extension() {
+        "???".to_string()
+    } else {
+        def.to_string()
+    }
+}
+
+#[derive(Default)]
+struct LineStats {
+    crlf: usize,
+    cr: usize,
+    lf: usize,
+    nel: usize,
+    max_line_len: usize,
+    has_escapes: bool,
+    has_backspace: bool,
+}
+
+impl LineStats {
+    fn new(text: &[u32]) -> Self {
+        let mut stats = LineStats::default();
+        let mut seen_cr = false;
+        // The index of the last line terminator, if there has been one.
+        let mut last_line_end = None;
+
+        for (idx, c) in text.iter().copied().enumerate() {
+            if c == u32::from(b'\n') {
+                if seen_cr {
+                    stats.crlf += 1;
+                } else {
+                    stats.lf += 1;
+                }
+                last_line_end = Some(idx);
+            } else if seen_cr {
+                stats.cr += 1;
+            }
+
+            seen_cr = c == u32::from(b'\r');
+            if seen_cr {
+                last_line_end = Some(idx);
+            }
+
+            if c == 0x85 {
+                stats.nel += 1;
+                last_line_end = Some(idx);
+            }
+
+            let line_len = match last_line_end {
+                Some(end) => idx - end,
+                None => idx + 1,
+            };
+            if line_len > MAX_LINE_LEN {
+                stats.max_line_len = stats.max_line_len.max(line_len);
+            }
+
+            stats.has_escapes |= c == 0x1b;
+            stats.has_backspace |= c == 0x08;
+        }
+
+        if seen_cr {
+            stats.cr += 1;
+        }
+
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::magic::tests::entry;
+    use crate::magic::Relation;
+    use crate::value::ValueType;
+
+    #[test]
+    fn check_fallback_descriptions() -> Result<()> {
+        let set = MagicSet::new(MagicMap::default());
+
+        assert_eq!(set.buffer(b"")?, "empty");
+        assert_eq!(set.buffer(b"a")?, "very short file (no magic)");
+        assert_eq!(set.buffer(b"\x00\x01\x02\x03")?, "data");
+        assert_eq!(set.buffer(b"hello\n")?, "ASCII text");
+        assert_eq!(
+            set.buffer(b"#!/bin/sh\r\n")?,
+            "ASCII text executable, with CRLF line terminators"
+        );
+        assert_eq!(
+            set.buffer("h\u{e9}llo".as_bytes())?,
+            "Unicode text, UTF-8 text, with no line terminators"
+        );
+
+        let long = [b'a'; 400];
+        assert_eq!(
+            set.buffer(&long)?,
+            "ASCII text, with very long lines (400), with no line terminators"
+        );
+        let mut long = [b'a'; 400].to_vec();
+        long.extend_from_slice(b"\nshort\n");
+        assert_eq!(
+            set.buffer(&long)?,
+            "ASCII text, with very long lines (400)"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_apple_type_parsing() {
+        assert_eq!(
+            AppleType::parse("8BIMPNGf"),
+            Some(AppleType {
+                creator: "8BIM".to_string(),
+                file_type: "PNGf".to_string(),
+            })
+        );
+        assert_eq!(
+            AppleType::parse("????TEXT"),
+            Some(AppleType {
+                creator: "????".to_string(),
+                file_type: "TEXT".to_string(),
+            })
+        );
+        assert_eq!(AppleType::parse("UNKNUNKN"), None);
+        assert_eq!(AppleType::parse(""), None);
+    }
+
+    #[test]
+    fn check_descriptor() -> Result<()> {
+        let mut set = MagicSet::new(MagicMap::default());
+        set.set_limits(Limits {
+            bytes_max: 1,
+            ..Limits::default()
+        });
+
+        let data: &[u8] = b"hello\n";
+        assert_eq!(set.descriptor(data)?, "very short file (no magic)");
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_mime() {
+        let mut png = entry(
+            0,
+            0,
+            ValueType::String,
+            Relation::Equal,
+            b"\x89PNG",
+            "PNG image data",
+        );
+        png.mimetype = "image/png".to_string();
+        let mut set = MagicSet::new(MagicMap::new(vec![png], Vec::new()));
+
+        set.set_flags(MagicSetFlags::MIME_TYPE);
+        assert_eq!(set.buffer(b"\x89PNG\r\n\x1a\n").unwrap(), "image/png");
+        assert_eq!(set.buffer(b"hello\n").unwrap(), "text/plain");
+        assert_eq!(
+            set.buffer(b"\x00\x01").unwrap(),
+            "application/octet-stream"
+        );
+        assert_eq!(set.buffer(b"").unwrap(), "application/x-empty");
+
+        set.set_flags(MagicSetFlags::MIME);
+        assert_eq!(
+            set.buffer(b"\x89PNG\r\n\x1a\n").unwrap(),
+            "image/png; charset=binary"
+        );
+        assert_eq!(
+            set.buffer(b"hello\n").unwrap(),
+            "text/plain; charset=us-ascii"
+        );
+        assert_eq!(
+            set.buffer("h\u{e9}llo".as_bytes()).unwrap(),
+            "text/plain; charset=utf-8"
+        );
+
+        set.set_flags(MagicSetFlags::MIME_ENCODING);
+        assert_eq!(set.buffer(b"hello\n").unwrap(), "us-ascii");
+    }
+
+    #[test]
+    fn check_extensions() {
+        let soi = (0xffd8u16 as i16 as i64).to_le_bytes();
+        let mut jpeg =
+            entry(0, 0, ValueType::BeShort, Relation::Equal, &soi, "JPEG");
+        jpeg.ext = "jpeg/jpg/jpe/jfif".to_string();
+        let mut set = MagicSet::new(MagicMap::new(vec![jpeg], Vec::new()));
+
+        let buf = b"\xff\xd8\xff\xe0\0\0";
+        assert_eq!(
+            set.extensions(buf).unwrap(),
+            vec!["jpeg", "jpg", "jpe", "jfif"]
+        );
+        assert!(set.extensions(b"\0\0\0").unwrap().is_empty());
+        assert!(set.extensions(b"hello\n").unwrap().is_empty());
+
+        set.set_flags(MagicSetFlags::EXTENSION);
+        assert_eq!(set.buffer(buf).unwrap(), "jpeg/jpg/jpe/jfif");
+        assert_eq!(set.buffer(b"\0\0\0").unwrap(), "???");
+    }
+
+    #[test]
+    fn check_apple() {
+        let mut png = entry(
+            0,
+            0,
+            ValueType::String,
+            Relation::Equal,
+            b"\x89PNG",
+            "PNG image data",
+        );
+        png.apple = "8BIMPNGf".to_string();
+        png.ext = "png".to_string();
+        let mut set = MagicSet::new(MagicMap::new(vec![png], Vec::new()));
+
+        let buf = b"\x89PNG\r\n\x1a\n";
+        let apple = set.apple(buf).unwrap().unwrap();
+        assert_eq!(apple.creator, "8BIM");
+        assert_eq!(apple.file_type, "PNGf");
+        assert_eq!(set.apple(b"\0\0\0").unwrap(), None);
+
+        // Apple codes take priority over the other annotations.
+        set.set_flags(MagicSetFlags::APPLE | MagicSetFlags::EXTENSION);
+        assert_eq!(set.buffer(buf).unwrap(), "8BIMPNGf");
+        assert_eq!(set.buffer(b"\0\0\0").unwrap(), "UNKNUNKN");
+    }
+}
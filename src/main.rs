@@ -0,0 +1,44 @@
+use std::io;
+use std::process::ExitCode;
+
+use magicrs::magic_set::MagicSet;
+
+const USAGE: &str = "\
+Usage: magicrs <command> [args]
+
+Commands:
+    list <magic.mgc>    List every top level entry of a compiled magic
+                        database with its strength, like `file -l`";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["list", path] => list(path),
+        ["-h" | "--help"] => {
+            println!("{}", USAGE);
+            ExitCode::SUCCESS
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn list(path: &str) -> ExitCode {
+    let set = match MagicSet::load(path) {
+        Ok(set) => set,
+        Err(e) => {
+            eprintln!("magicrs: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Err(e) = set.map().list(&mut io::stdout().lock()) {
+        eprintln!("magicrs: {}", e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,138 @@
+use regex::bytes::{Regex, RegexBuilder};
+
+/// Compile a POSIX extended regular expression from a `regex` test the way
+/// libmagic does, i.e., with `REG_EXTENDED | REG_NEWLINE` and optionally
+/// `REG_ICASE`.
+pub(crate) fn compile(
+    pattern: &[u8],
+    icase: bool,
+) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(&translate(pattern))
+        .unicode(false)
+        .multi_line(true)
+        .case_insensitive(icase)
+        .build()
+}
+
+/// Rewrite a POSIX extended regular expression in the syntax of the regex
+/// crate. The two mostly agree, but POSIX treats a backslash inside a
+/// bracket expression and a brace that doesn't start a bound literally,
+/// and patterns may contain bytes that aren't valid UTF-8.
+fn translate(pattern: &[u8]) -> String {
+    let mut out = String::with_capacity(pattern.len());
+    let mut idx = 0;
+    while idx < pattern.len() {
+        let b = pattern[idx];
+        match b {
+            b'[' => idx = translate_bracket(pattern, idx, &mut out),
+            b'\\' if idx + 1 < pattern.len() => {
+                // An escaped byte that isn't ASCII is just that byte.
+                if pattern[idx + 1].is_ascii() {
+                    out.push('\\');
+                }
+                push_byte(&mut out, pattern[idx + 1]);
+                idx += 2;
+            }
+            b'{' if !pattern
+                .get(idx + 1)
+                .is_some_and(|next| next.is_ascii_digit()) =>
+            {
+                out.push_str("\\{");
+                idx += 1;
+            }
+            _ => {
+                push_byte(&mut out, b);
+                idx += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Translate the bracket expression starting at `start`, returning the
+/// index just past it. An unterminated bracket is copied through for the
+/// regex crate to reject.
+fn translate_bracket(pattern: &[u8], start: usize, out: &mut String) -> usize {
+    let mut idx = start + 1;
+    out.push('[');
+    if pattern.get(idx) == Some(&b'^') {
+        // With `REG_NEWLINE` a negated set never matches a newline.
+        out.push_str("^\\n");
+        idx += 1;
+    }
+    // A leading ']' is part of the set.
+    if pattern.get(idx) == Some(&b']') {
+        out.push_str("\\]");
+        idx += 1;
+    }
+
+    while idx < pattern.len() {
+        match pattern[idx] {
+            b']' => {
+                out.push(']');
+                return idx + 1;
+            }
+            b'[' if matches!(
+                pattern.get(idx + 1),
+                Some(b':' | b'.' | b'=')
+            ) =>
+            {
+                // Character classes like `[:alpha:]` are copied as is.
+                let kind = pattern[idx + 1];
+                let end = pattern[idx + 2..]
+                    .windows(2)
+                    .position(|w| w[0] == kind && w[1] == b']')
+                    .map_or(pattern.len(), |pos| idx + 2 + pos + 2);
+                for b in &pattern[idx..end] {
+                    push_byte(out, *b);
+                }
+                idx = end;
+            }
+            // Characters with a special meaning in the regex crate's sets.
+            b @ (b'\\' | b'[' | b'&' | b'~') => {
+                out.push('\\');
+                out.push(b as char);
+                idx += 1;
+            }
+            b => {
+                push_byte(out, b);
+                idx += 1;
+            }
+        }
+    }
+    idx
+}
+
+fn push_byte(out: &mut String, b: u8) {
+    if b.is_ascii() {
+        out.push(b as char);
+    } else {
+        out.push_str(&format!("\\x{:02x}", b));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_translation() {
+        assert_eq!(translate(b"^#!/bin/[a-z]+"), "^#!/bin/[a-z]+");
+        assert_eq!(translate(b"[\\/]x{2}"), "[\\\\/]x{2}");
+        assert_eq!(translate(b"[]a]"), "[\\]a]");
+        assert_eq!(translate(b"[^[:space:]]"), "[^\\n[:space:]]");
+        assert_eq!(translate(b"[^]a]"), "[^\\n\\]a]");
+        assert_eq!(translate(b"a{b"), "a\\{b");
+        assert_eq!(translate(b"\xff\\."), "\\xff\\.");
+
+        let regex = compile(b"^begin [0-7]+ ", false).unwrap();
+        assert!(regex.is_match(b"junk\nbegin 644 file"));
+        assert!(!regex.is_match(b"junk begin 644 file"));
+        let regex = compile(b"a[^x]b", false).unwrap();
+        assert!(regex.is_match(b"a-b"));
+        assert!(!regex.is_match(b"a\nb"));
+        assert!(compile(b"BEGIN", true).unwrap().is_match(b"begin"));
+        assert!(compile(b"\xff.", false).unwrap().is_match(b"\xff\xfe"));
+        assert!(compile(b"(", false).is_err());
+    }
+}
@@ -0,0 +1,2360 @@
+use memchr::memmem;
+use thiserror::Error;
+
+use crate::date::{self, Clock};
+use crate::der;
+use crate::format::{self, Arg};
+use crate::magic::{ConditionalType, IndirectionOperator, Magic, Relation};
+use crate::magic_set::{MagicSet, MagicSetFlags};
+use crate::traits::{
+    ReadBigEndian, ReadHostOrder, ReadLittleEndian, ReadMiddleEndian,
+};
+use crate::value::{Guid, StringFlags, ValueOption, ValueType};
+
+/// The largest string libmagic copies out of the buffer for a string test,
+/// not including the terminating null byte.
+const MAX_STRING: usize = 127;
+
+#[derive(Debug, Error)]
+pub enum MatchError {
+    #[error("Division by zero in mask of magic entry on line {0}")]
+    DivideByZero(u32),
+    #[error("Indirect count ({0}) exceeded")]
+    IndirectionLimit(u16),
+    #[error("Name use count ({0}) exceeded")]
+    NameLimit(u16),
+    #[error("Cannot find entry `{0}'")]
+    UnknownName(String),
+    #[error("Regex error for `{0}', ({1})")]
+    InvalidRegex(String, String),
+}
+
+type Result<T> = std::result::Result<T, MatchError>;
+
+/// libmagic flags every top level entry as either a binary or a text test
+/// and only evaluates one kind in each pass over the database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestType {
+    Binary,
+    Text,
+}
+
+impl TestType {
+    /// Whether the top level entry `m` is evaluated in this kind of pass.
+    pub fn includes(&self, m: &Magic) -> bool {
+        match self {
+            TestType::Binary => m.flags.is_bin_test(),
+            TestType::Text => m.flags.is_text_test(),
+        }
+    }
+}
+
+/// Evaluate the top level entries of `set` that match `test_type` against
+/// `buf`, returning the assembled description of the first entry that
+/// produced any output, or of every such entry when `CONTINUE` is set.
+pub(crate) fn softmagic(
+    set: &MagicSet,
+    flags: MagicSetFlags,
+    buf: &[u8],
+    test_type: TestType,
+) -> Result<Option<String>> {
+    let mut matcher = Matcher::new(set, flags, buf, test_type);
+    if matcher.match_entries(set.map().left())? {
+        Ok(Some(matcher.output))
+    } else {
+        Ok(None)
+    }
+}
+
+/// The value read out of the buffer for a single test.
+enum Data {
+    /// A numeric value after the mask has been applied but before any sign
+    /// extension.
+    Numeric(u64),
+    /// A `float` or `double` value after the mask has been applied. Floats
+    /// are widened losslessly.
+    Float(f64),
+    /// The bytes copied out of the buffer for a string test.
+    Bytes(Vec<u8>),
+    /// The outcome of a `search` or `regex` test: how far past the offset
+    /// the pattern was found, if at all, along with the data to display,
+    /// which is the data from there on for a `search` and the matched text
+    /// for a `regex`.
+    Search(Option<(usize, Vec<u8>)>),
+    /// The value read for a `guid` test.
+    Guid(Guid),
+    /// The outcome of a `der` test: the rendered contents of the value if
+    /// it matched, which are empty unless the test compared them.
+    Der(Option<String>),
+    /// The rendered output of a successful `indirect` test.
+    Indirect(String),
+    /// Tests like `default` and `clear` that don't read any data.
+    None,
+}
+
+/// The state libmagic keeps for each continuation level.
+#[derive(Clone, Copy, Default)]
+struct LevelInfo {
+    /// The offset just past the data matched at this level, which `&`
+    /// offsets on the next level are relative to.
+    offset: usize,
+    /// Whether any test at this level has matched since the level was last
+    /// entered. Used by `default` and `clear`.
+    got_match: bool,
+    /// Whether the last test evaluated at this level matched. Used by `elif`
+    /// and `else`.
+    last_match: bool,
+}
+
+struct Matcher<'a> {
+    set: &'a MagicSet,
+    buf: &'a [u8],
+    flags: MagicSetFlags,
+    test_type: TestType,
+    output: String,
+    need_separator: bool,
+    printed_something: bool,
+    firstline: bool,
+    /// Whether anything has been printed, shared with named subroutines.
+    returnval: bool,
+    /// Whether any entry with a description has matched, shared with named
+    /// subroutines.
+    found_match: bool,
+    levels: Vec<LevelInfo>,
+    /// The offset of the current test.
+    offset: usize,
+    /// The offset at the end of the file continuations are relative to when
+    /// the top level entry has a negative offset.
+    eoffset: usize,
+    /// The offset of the `use` test that invoked the current named
+    /// subroutine, which offsets in the subroutine are relative to.
+    base: usize,
+    /// Whether the endianness of numeric types is swapped by a `^name`
+    /// subroutine call.
+    flip: bool,
+    /// The number of `indirect` tests evaluated so far.
+    indirect_count: u16,
+    /// The depth of named subroutine calls.
+    name_count: u16,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(
+        set: &'a MagicSet,
+        flags: MagicSetFlags,
+        buf: &'a [u8],
+        test_type: TestType,
+    ) -> Self {
+        Self {
+            set,
+            buf,
+            flags,
+            test_type,
+            output: String::new(),
+            need_separator: false,
+            printed_something: false,
+            firstline: true,
+            returnval: false,
+            found_match: false,
+            levels: Vec::new(),
+            offset: 0,
+            eoffset: 0,
+            base: 0,
+            flip: false,
+            indirect_count: 0,
+            name_count: 0,
+        }
+    }
+
+    fn match_entries(&mut self, entries: &[Magic]) -> Result<bool> {
+        let mut idx = 0;
+
+        while idx < entries.len() {
+            let m = &entries[idx];
+            let next = skip_continuations(entries, idx);
+
+            // Named subroutines are run whatever the type of test.
+            if !self.test_type.includes(m)
+                && !matches!(m.value_type, ValueType::Name)
+            {
+                idx = next;
+                continue;
+            }
+
+            self.enter_level(0);
+            if !self.set_offset(m, 0) {
+                idx = next;
+                continue;
+            }
+
+            let Some(data) = self.test(m, 0)? else {
+                idx = next;
+                continue;
+            };
+
+            if self.annotate(m, &data) {
+                return Ok(true);
+            }
+
+            if has_output(m, &data) {
+                self.found_match = true;
+                if self.flags.prints_description() {
+                    self.returnval = true;
+                    self.need_separator = true;
+                    self.printed_something = true;
+                    self.print_separator();
+                    self.print(m, &data);
+                }
+            }
+
+            if !self.end_offset(m, &data, 0) {
+                idx = next;
+                continue;
+            }
+
+            let mut cont_level = 1;
+            self.enter_level(cont_level);
+
+            idx += 1;
+            while idx < next {
+                let m = &entries[idx];
+                idx += 1;
+
+                let level = m.cont_level as usize;
+                if cont_level < level {
+                    continue;
+                }
+
+                if cont_level > level {
+                    // We're at the end of the continuations for the
+                    // previous level.
+                    cont_level = level;
+                }
+
+                if !self.set_offset(m, cont_level) {
+                    // An offset outside the buffer leaves nothing to test,
+                    // just like a value that runs past its end.
+                    continue;
+                }
+
+                // Only the first matching branch of a conditional chain is
+                // evaluated.
+                if matches!(
+                    m.conditional_type,
+                    ConditionalType::Elif | ConditionalType::Else
+                ) && self.levels[cont_level].last_match
+                {
+                    continue;
+                }
+
+                let Some(data) = self.test(m, cont_level)? else {
+                    continue;
+                };
+
+                let level = &mut self.levels[cont_level];
+                if matches!(m.value_type, ValueType::Clear) {
+                    level.got_match = false;
+                } else if level.got_match {
+                    if matches!(m.value_type, ValueType::Default) {
+                        continue;
+                    }
+                } else {
+                    level.got_match = true;
+                }
+
+                if self.annotate(m, &data) {
+                    return Ok(true);
+                }
+
+                if has_output(m, &data) {
+                    self.found_match = true;
+                }
+
+                if self.flags.prints_description() && has_output(m, &data) {
+                    self.returnval = true;
+                    if !self.printed_something {
+                        self.printed_something = true;
+                        self.print_separator();
+                    }
+                    if self.need_separator && !m.flags.is_no_space() {
+                        self.output.push(' ');
+                    }
+                    self.print(m, &data);
+                    self.need_separator = true;
+                }
+
+                // When the end offset can't be computed libmagic drops back a
+                // level, skipping this entry's own continuations while still
+                // evaluating its siblings.
+                if self.end_offset(m, &data, cont_level) {
+                    cont_level += 1;
+                }
+                self.enter_level(cont_level);
+            }
+
+            if self.printed_something {
+                self.firstline = false;
+            }
+
+            if self.found_match {
+                if !self.flags.is_continue() {
+                    return Ok(self.returnval);
+                }
+                // So that the next match is separated from this one.
+                self.printed_something = false;
+                self.firstline = false;
+            }
+
+            idx = next;
+        }
+
+        Ok(self.returnval)
+    }
+
+    /// In the output modes that report an annotation of the matched records
+    /// instead of their descriptions the first annotation found wins.
+    fn annotate(&mut self, m: &Magic, data: &Data) -> bool {
+        let annotation = if let Data::Indirect(output) = data {
+            // The annotation of the indirect match has already been found.
+            if self.flags.prints_description() || output.is_empty() {
+                return false;
+            }
+            output
+        } else if self.flags.is_apple() && !m.apple.is_empty() {
+            &m.apple
+        } else if self.flags.is_extension() && !m.ext.is_empty() {
+            &m.ext
+        } else if self.flags.is_mime_type() && !m.mimetype.is_empty() {
+            &m.mimetype
+        } else {
+            return false;
+        };
+
+        self.print_separator();
+        self.output.push_str(annotation);
+        self.found_match = true;
+        self.need_separator = true;
+        self.printed_something = true;
+        self.returnval = true;
+        true
+    }
+
+    /// Separate the output of each top level match when `CONTINUE` is set.
+    fn print_separator(&mut self) {
+        if !self.firstline {
+            self.output.push_str("\n- ");
+        }
+    }
+
+    fn enter_level(&mut self, level: usize) {
+        if level >= self.levels.len() {
+            self.levels.resize(level + 1, LevelInfo::default());
+        }
+        self.levels[level].got_match = false;
+        self.levels[level].last_match = false;
+    }
+
+    /// Compute the offset in the buffer that `m` tests before any
+    /// indirection, returning false if it lies outside the buffer.
+    fn set_offset(&mut self, m: &Magic, cont_level: usize) -> bool {
+        let relative =
+            m.flags.is_offset_add() || m.flags.is_indirect_offset_add();
+        let magnitude = m.offset.unsigned_abs() as usize;
+
+        if m.flags.is_offset_negative() && (cont_level == 0 || !relative) {
+            // An offset from the end of the file.
+            let Some(offset) = self.buf.len().checked_sub(magnitude) else {
+                return false;
+            };
+            self.offset = offset;
+            self.eoffset = offset;
+        } else {
+            let offset = if m.flags.is_offset_negative() {
+                m.offset.unsigned_abs().wrapping_neg()
+            } else {
+                m.offset as u32
+            };
+            if cont_level == 0 || m.flags.is_offset_negative() {
+                self.offset = offset as usize;
+                self.eoffset = 0;
+            } else {
+                self.offset = add_offset(self.eoffset, offset);
+            }
+        }
+
+        if m.flags.is_offset_add() {
+            if cont_level == 0 {
+                return false;
+            }
+            self.offset = add_offset(
+                self.levels[cont_level - 1].offset,
+                self.offset as u32,
+            );
+        }
+
+        true
+    }
+
+    /// Record the offset just past the data matched by `m` for relative
+    /// offsets on the next level, returning false if it lies outside the
+    /// buffer.
+    fn end_offset(
+        &mut self,
+        m: &Magic,
+        data: &Data,
+        cont_level: usize,
+    ) -> bool {
+        let size = type_size(m.value_type).or_else(|| float_size(m.value_type));
+        let offset = if let Some(size) = size {
+            self.offset + size
+        } else {
+            match (m.value_type, data) {
+                (
+                    ValueType::String
+                    | ValueType::PString
+                    | ValueType::BeString16
+                    | ValueType::LeString16,
+                    Data::Bytes(bytes),
+                ) => {
+                    if matches!(
+                        m.relation,
+                        Relation::Equal | Relation::NotEqual
+                    ) {
+                        // libmagic doesn't skip the length prefix of a
+                        // pstring here.
+                        self.offset + m.value_len as usize
+                    } else if matches!(m.value_type, ValueType::PString) {
+                        let size = m.string_flags().pstring_length_size();
+                        self.offset + size + string_data(m, bytes).len()
+                    } else {
+                        self.offset + string_data(m, bytes).len()
+                    }
+                }
+                (ValueType::Search, Data::Search(Some((idx, _)))) => {
+                    if m.string_flags().is_regex_offset_start() {
+                        self.offset + idx
+                    } else {
+                        self.offset + idx + m.value_len as usize
+                    }
+                }
+                (ValueType::Regex, Data::Search(Some((idx, matched)))) => {
+                    if m.string_flags().is_regex_offset_start() {
+                        self.offset + idx
+                    } else {
+                        self.offset + idx + matched.len()
+                    }
+                }
+                (ValueType::Guid, _) => self.offset + 16,
+                (ValueType::BeVarInt | ValueType::LeVarInt, _) => {
+                    let start = add_offset(self.base, self.offset as u32);
+                    let Some((_, len)) = self
+                        .buf
+                        .get(start..)
+                        .and_then(|bytes| read_varint(m.value_type, bytes))
+                    else {
+                        return false;
+                    };
+                    self.offset + len
+                }
+                (ValueType::Der, _) => {
+                    // The next level continues with the contents of the
+                    // value while the following entries on this level
+                    // continue after it.
+                    let start = add_offset(self.base, self.offset as u32);
+                    let Some(header) =
+                        self.buf.get(start..).and_then(der::Header::read)
+                    else {
+                        return false;
+                    };
+                    // Like libmagic's `der_offs` this adds the offset of
+                    // the entry again, on top of the offset it was read at.
+                    let contents =
+                        add_offset(self.offset + header.size, m.offset as u32);
+                    if cont_level != 0 {
+                        let end = contents + header.len;
+                        if end > self.buf.len() {
+                            return false;
+                        }
+                        self.levels[cont_level - 1].offset = end;
+                    }
+                    contents
+                }
+                (
+                    ValueType::Indirect
+                    | ValueType::Default
+                    | ValueType::Clear
+                    | ValueType::Name
+                    | ValueType::Use
+                    | ValueType::Offset,
+                    _,
+                ) => self.offset,
+                _ => 0,
+            }
+        };
+
+        if offset > self.buf.len() {
+            return false;
+        }
+        self.levels[cont_level].offset = offset;
+        true
+    }
+
+    /// Read the data for `m` at `offset` and check it against the entry's
+    /// value, returning the data read when the test matches.
+    fn test(&mut self, m: &Magic, cont_level: usize) -> Result<Option<Data>> {
+        let (data, matched) = match self.get(m, cont_level)? {
+            Some(data) => {
+                let matched = self.check(m, &data);
+                (data, matched)
+            }
+            // libmagic treats a not-equal test as successful without
+            // checking anything when there is no data to compare against.
+            None if matches!(m.relation, Relation::NotEqual) => {
+                let data = if type_size(m.value_type).is_some() {
+                    Data::Numeric(0)
+                } else if float_size(m.value_type).is_some() {
+                    Data::Float(0.0)
+                } else {
+                    Data::Bytes(Vec::new())
+                };
+                (data, true)
+            }
+            // Nothing was tested, so the previous result on this level still
+            // decides whether an `elif` or `else` runs.
+            None => return Ok(None),
+        };
+
+        self.levels[cont_level].last_match = matched;
+        Ok(matched.then_some(data))
+    }
+
+    fn get(&mut self, m: &Magic, cont_level: usize) -> Result<Option<Data>> {
+        // Offsets inside a named subroutine are relative to the `use` entry
+        // that called it, although the result of an indirection isn't.
+        let mut offset = add_offset(self.base, self.offset as u32);
+        if m.flags.is_indirect() {
+            let Some(indirect) = self.indirect_offset(m, offset) else {
+                return Ok(None);
+            };
+            offset = indirect as usize;
+            if m.flags.is_indirect_offset_add() {
+                if cont_level == 0 {
+                    return Ok(None);
+                }
+                offset =
+                    add_offset(self.levels[cont_level - 1].offset, indirect);
+            }
+            self.offset = offset;
+        }
+
+        if let Some(size) = type_size(m.value_type) {
+            let Some(bytes) = self.buf.get(offset..offset + size) else {
+                return Ok(None);
+            };
+            let value = read_numeric(self.flip(m.value_type), bytes);
+            return Ok(Some(Data::Numeric(apply_mask(m, value)?)));
+        }
+
+        if let Some(size) = float_size(m.value_type) {
+            let Some(bytes) = self.buf.get(offset..offset + size) else {
+                return Ok(None);
+            };
+            let value = read_float(self.flip(m.value_type), bytes);
+            return Ok(Some(Data::Float(apply_float_mask(m, value))));
+        }
+
+        let data = match m.value_type {
+            ValueType::String | ValueType::PString => {
+                let vallen = m.value_len as usize;
+                if offset > self.buf.len() || vallen > self.buf.len() - offset {
+                    return Ok(None);
+                }
+                if matches!(m.value_type, ValueType::PString) {
+                    let end = self.buf.len().min(offset + MAX_STRING + 1);
+                    let bytes = &self.buf[offset..end];
+                    match pstring(m.string_flags(), bytes) {
+                        Some(string) => Data::Bytes(string),
+                        None => return Ok(None),
+                    }
+                } else {
+                    let end = self.buf.len().min(offset + MAX_STRING);
+                    Data::Bytes(self.buf[offset..end].to_vec())
+                }
+            }
+            ValueType::BeString16 | ValueType::LeString16 => {
+                let vallen = m.value_len as usize;
+                if offset >= self.buf.len() || vallen > self.buf.len() - offset
+                {
+                    return Ok(None);
+                }
+                let big_endian = matches!(m.value_type, ValueType::BeString16);
+                Data::Bytes(string16(&self.buf[offset..], big_endian))
+            }
+            ValueType::Search => {
+                if offset > self.buf.len() {
+                    return Ok(None);
+                }
+                let range = match m.string_range() as usize {
+                    0 => self.set.limits().search_max,
+                    range => range.min(self.set.limits().search_max),
+                };
+                let pattern = &m.value.bytes()[..m.value_len as usize];
+                let data = &self.buf[offset..];
+                let found = search(pattern, data, range, m.string_flags());
+                Data::Search(found.map(|idx| {
+                    let end = data.len().min(idx + MAX_STRING);
+                    (idx, data[idx..end].to_vec())
+                }))
+            }
+            ValueType::Regex => {
+                if offset > self.buf.len() {
+                    return Ok(None);
+                }
+                let regex = match self.set.map().regex(m) {
+                    Some(Ok(regex)) => regex,
+                    // `MagicMap::new` compiles every `regex` entry of the
+                    // map, so only an entry from another map is missing.
+                    None => return Ok(None),
+                    Some(Err(err)) => {
+                        let pattern = &m.value.bytes()[..m.value_len as usize];
+                        return Err(MatchError::InvalidRegex(
+                            String::from_utf8_lossy(pattern).into_owned(),
+                            err.to_string(),
+                        ));
+                    }
+                };
+                let max = self.set.limits().regex_max as usize;
+                let window = regex_window(m, &self.buf[offset..], max);
+                Data::Search(
+                    regex.find(window).map(|found| {
+                        (found.start(), found.as_bytes().to_vec())
+                    }),
+                )
+            }
+            ValueType::BeVarInt | ValueType::LeVarInt => {
+                let Some((value, _)) = self
+                    .buf
+                    .get(offset..)
+                    .and_then(|bytes| read_varint(m.value_type, bytes))
+                else {
+                    return Ok(None);
+                };
+                Data::Numeric(apply_mask(m, value)?)
+            }
+            ValueType::Guid => {
+                let Some(bytes) = self.buf.get(offset..offset + 16) else {
+                    return Ok(None);
+                };
+                Data::Guid(Guid::from_bytes(bytes))
+            }
+            ValueType::Der => {
+                let data = self.buf.get(offset..).unwrap_or_default();
+                Data::Der(der::compare(data, m.value.string()))
+            }
+            ValueType::Indirect => {
+                // Unlike other tests, the offset of an `indirect` test is
+                // only relative to the calling `use` entry with `r`.
+                let mut offset = self.offset;
+                if m.string_flags().is_indirect_relative() {
+                    offset = add_offset(self.base, offset as u32);
+                }
+                if offset == 0 || offset > self.buf.len() {
+                    return Ok(None);
+                }
+                match self.indirect(m, offset)? {
+                    Some(output) => Data::Indirect(output),
+                    None => return Ok(None),
+                }
+            }
+            ValueType::Use => {
+                let base = add_offset(self.base, self.offset as u32);
+                if !self.call(m, base)? {
+                    return Ok(None);
+                }
+                Data::None
+            }
+            _ => Data::None,
+        };
+
+        Ok(Some(data))
+    }
+
+    /// Follow the pointer described by an indirect offset such as
+    /// `(0x3c.l+4)` or `(4.s*(8.b))`, returning the offset it points to.
+    ///
+    /// Like libmagic the result is truncated to an unsigned 32 bit offset,
+    /// so a negative one wraps around rather than failing here.
+    fn indirect_offset(&self, m: &Magic, offset: usize) -> Option<u32> {
+        let signed = m.indirection_operation.flags.signed;
+        let in_type = self.flip(m.indirection_type);
+        let mut operand = m.indirection_offset as i64;
+
+        // The operand itself is read from the buffer relative to the offset.
+        if m.indirection_operation.flags.indirect {
+            let at = offset.checked_add_signed(operand as isize)?;
+            operand = self.read_pointer(in_type, at, signed)?;
+        }
+
+        let pointer = self.read_pointer(in_type, offset, signed)?;
+
+        // libmagic refuses to operate on anything that doesn't fit in 32
+        // bits.
+        let range = (i32::MIN as i64 + 1)..(u32::MAX as i64);
+        if !range.contains(&pointer) || !range.contains(&operand) {
+            return None;
+        }
+
+        let mut offset = if operand == 0 {
+            pointer
+        } else {
+            match m.indirection_operation.op {
+                IndirectionOperator::And => pointer & operand,
+                IndirectionOperator::Or => pointer | operand,
+                IndirectionOperator::Xor => pointer ^ operand,
+                IndirectionOperator::Add => pointer + operand,
+                IndirectionOperator::Subtract => pointer - operand,
+                IndirectionOperator::Multiply => pointer.wrapping_mul(operand),
+                IndirectionOperator::Divide => pointer / operand,
+                IndirectionOperator::Modulo => pointer % operand,
+            }
+        };
+
+        if m.indirection_operation.flags.inverse {
+            offset = !offset;
+        }
+
+        if offset >= u32::MAX as i64 {
+            return None;
+        }
+        Some(offset as u32)
+    }
+
+    /// Read a pointer of type `vtype` for an indirect offset.
+    fn read_pointer(
+        &self,
+        vtype: ValueType,
+        offset: usize,
+        signed: bool,
+    ) -> Option<i64> {
+        use ValueType::*;
+
+        if !matches!(
+            vtype,
+            Byte | Short
+                | BeShort
+                | LeShort
+                | Long
+                | BeLong
+                | LeLong
+                | MeLong
+                | Quad
+                | BeQuad
+                | LeQuad
+                | BeId3
+                | LeId3
+        ) {
+            return None;
+        }
+
+        let size = type_size(vtype)?;
+        let bytes = self.buf.get(offset..offset.checked_add(size)?)?;
+        let value = read_numeric(vtype, bytes);
+        if signed {
+            Some(sign_extend(vtype, value) as i64)
+        } else {
+            Some(value as i64)
+        }
+    }
+
+    /// Evaluate the whole database against the buffer starting at `offset`
+    /// for an `indirect` test, returning the rendered output if anything
+    /// matched.
+    fn indirect(&mut self, m: &Magic, offset: usize) -> Result<Option<String>> {
+        let max = self.set.limits().indirection_max;
+        if self.indirect_count >= max {
+            return Err(MatchError::IndirectionLimit(max));
+        }
+
+        // The nested pass keeps its own output and level state as it's a
+        // separate invocation of the matcher in libmagic.
+        let buf = &self.buf[offset..];
+        let mut matcher =
+            Matcher::new(self.set, self.flags, buf, TestType::Binary);
+        matcher.indirect_count = self.indirect_count + 1;
+        matcher.name_count = self.name_count;
+        let matched = matcher.match_entries(self.set.map().left())?;
+        self.indirect_count = matcher.indirect_count;
+        if !matched {
+            return Ok(None);
+        }
+
+        let mut rendered = String::new();
+        if self.flags.prints_description() {
+            rendered = format::format(&m.desc, Arg::Int(offset as u64, 32));
+        }
+        rendered.push_str(&matcher.output);
+        Ok(Some(rendered))
+    }
+
+    /// Run the named subroutine called by the `use` entry `m` with offsets
+    /// relative to `base`, returning whether anything has been printed.
+    fn call(&mut self, m: &Magic, base: usize) -> Result<bool> {
+        if base > self.buf.len() {
+            return Ok(false);
+        }
+
+        let max = self.set.limits().name_max;
+        if self.name_count >= max {
+            return Err(MatchError::NameLimit(max));
+        }
+
+        let (name, flip) = match m.value.string() {
+            [b'^', name @ ..] => (name, true),
+            name => (name, false),
+        };
+        let set = self.set;
+        let Some(entries) = set.map().named(name) else {
+            let name = String::from_utf8_lossy(name);
+            return Err(MatchError::UnknownName(name.into_owned()));
+        };
+
+        let need_separator = self.need_separator;
+        if m.flags.is_no_space() {
+            self.need_separator = false;
+        }
+
+        // The subroutine shares the output state of the caller but has its
+        // own levels and offsets.
+        let found_match = std::mem::take(&mut self.found_match);
+        let levels = std::mem::take(&mut self.levels);
+        let (offset, eoffset) = (self.offset, self.eoffset);
+        let (old_base, old_flip) = (self.base, self.flip);
+        self.base = base;
+        self.flip ^= flip;
+        self.name_count += 1;
+
+        let matched = self.match_entries(entries);
+
+        self.name_count -= 1;
+        self.base = old_base;
+        self.flip = old_flip;
+        self.offset = offset;
+        self.eoffset = eoffset;
+        self.levels = levels;
+        self.found_match |= found_match;
+
+        let matched = matched?;
+        if !matched {
+            self.need_separator = need_separator;
+        }
+        Ok(matched)
+    }
+
+    /// The type to read for `vtype` taking the byte order of the current
+    /// named subroutine into account.
+    fn flip(&self, vtype: ValueType) -> ValueType {
+        if self.flip {
+            vtype.flipped()
+        } else {
+            vtype
+        }
+    }
+
+    fn check(&self, m: &Magic, data: &Data) -> bool {
+        let (value, expected) = match data {
+            Data::Numeric(value) => {
+                let expected =
+                    m.numeric_value().and_then(|v| v.as_integer()).unwrap_or(0);
+                if m.flags.is_unsigned() {
+                    (*value, expected)
+                } else {
+                    (sign_extend(m.value_type, *value), expected)
+                }
+            }
+            Data::Bytes(bytes) => {
+                let pattern = &m.value.bytes()[..m.value_len as usize];
+                // libmagic ignores the modifiers of 16-bit strings.
+                let flags = match m.value_type {
+                    ValueType::BeString16 | ValueType::LeString16 => {
+                        StringFlags::default()
+                    }
+                    _ => m.string_flags(),
+                };
+                (strncmp(pattern, bytes, MAX_STRING + 1, flags) as u64, 0)
+            }
+            // A search that found nothing compares like a mismatched string.
+            Data::Search(found) => (found.is_none() as u64, 0),
+            Data::Guid(guid) => {
+                // Compared like memcmp(3) with the expected value first.
+                let expected = m.value.guid().map(|g| *g.bytes());
+                let diff = expected
+                    .unwrap_or_default()
+                    .iter()
+                    .zip(guid.bytes())
+                    .find(|(a, b)| a != b)
+                    .map_or(0, |(a, b)| *a as i64 - *b as i64);
+                (diff as u64, 0)
+            }
+            Data::Float(value) => return check_float(m, *value),
+            // The relation plays no part in a `der` test.
+            Data::Der(found) => return found.is_some(),
+            Data::Indirect(_) => return true,
+            Data::None => {
+                return matches!(
+                    m.value_type,
+                    ValueType::Default
+                        | ValueType::Clear
+                        | ValueType::Name
+                        | ValueType::Use
+                )
+            }
+        };
+
+        // String comparisons are never unsigned.
+        let unsigned =
+            m.flags.is_unsigned() && matches!(data, Data::Numeric(_));
+
+        match m.relation {
+            Relation::Anything => true,
+            Relation::NotEqual => value != expected,
+            Relation::Equal => value == expected,
+            Relation::Greater if unsigned => value > expected,
+            Relation::Greater => (value as i64) > (expected as i64),
+            Relation::Lesser if unsigned => value < expected,
+            Relation::Lesser => (value as i64) < (expected as i64),
+            Relation::BitAnd => value & expected == expected,
+            Relation::BitXor => value & expected != expected,
+        }
+    }
+
+    fn print(&mut self, m: &Magic, data: &Data) {
+        let formatted = match data {
+            Data::Numeric(value) => match format_date(m.value_type, *value) {
+                Some(date) => format::format(&m.desc, Arg::Str(&date)),
+                None => {
+                    let bits = match (type_size(m.value_type), m.value_type) {
+                        (Some(8), _) => 64,
+                        (_, ValueType::BeVarInt | ValueType::LeVarInt) => 64,
+                        _ => 32,
+                    };
+                    format::format(&m.desc, Arg::Int(*value, bits))
+                }
+            },
+            Data::Float(value) => format::format(&m.desc, Arg::Float(*value)),
+            Data::Bytes(bytes) => {
+                let bytes = match m.relation {
+                    Relation::Equal | Relation::NotEqual => {
+                        m.value.bytes()[..m.value_len as usize].to_vec()
+                    }
+                    _ if m.string_flags().is_trim() => {
+                        trim(&string_data(m, bytes)).to_vec()
+                    }
+                    _ => string_data(m, bytes),
+                };
+                let printable = if self.flags.is_raw() {
+                    raw(&bytes)
+                } else {
+                    printable(&bytes)
+                };
+                format::format(&m.desc, Arg::Str(&printable))
+            }
+            Data::Search(found) => {
+                let bytes = match found {
+                    Some((_, bytes)) => string_data(m, bytes),
+                    None => Vec::new(),
+                };
+                let printable = if self.flags.is_raw() {
+                    raw(&bytes)
+                } else {
+                    printable(&bytes)
+                };
+                format::format(&m.desc, Arg::Str(&printable))
+            }
+            Data::Guid(guid) => {
+                format::format(&m.desc, Arg::Str(&guid.to_string()))
+            }
+            Data::Der(found) => {
+                let rendered = found.as_deref().unwrap_or_default();
+                let printable = printable(rendered.as_bytes());
+                format::format(&m.desc, Arg::Str(&printable))
+            }
+            Data::Indirect(output) => output.clone(),
+            Data::None => m.desc.clone(),
+        };
+        self.output.push_str(&formatted);
+    }
+}
+
+/// Add a possibly negative 32 bit offset to `base` with the wrapping
+/// arithmetic of libmagic's unsigned offsets.
+fn add_offset(base: usize, offset: u32) -> usize {
+    (base as u32).wrapping_add(offset) as usize
+}
+
+/// Whether a matched test produces any output.
+fn has_output(m: &Magic, data: &Data) -> bool {
+    !m.desc.is_empty() || matches!(data, Data::Indirect(_))
+}
+
+/// Return the index of the next top level entry after `idx`.
+fn skip_continuations(entries: &[Magic], idx: usize) -> usize {
+    let mut next = idx + 1;
+    while next < entries.len() && entries[next].cont_level != 0 {
+        next += 1;
+    }
+    next
+}
+
+/// The number of bytes read for a fixed size integer type.
+fn type_size(vtype: ValueType) -> Option<usize> {
+    vtype.size().filter(|_| vtype.is_integer())
+}
+
+fn read_numeric(vtype: ValueType, bytes: &[u8]) -> u64 {
+    use ValueType::*;
+    match vtype {
+        Byte => bytes[0] as u64,
+        Short | MSDosDate | MSDosTime => u16::read_ne(bytes) as u64,
+        BeShort | BeMsDosDate | BeMSDOSTime => u16::read_be(bytes) as u64,
+        LeShort | LeMSDosDate | LeMSDOSTime => u16::read_le(bytes) as u64,
+        Long | Date | LDate => u32::read_ne(bytes) as u64,
+        BeLong | BeDate | BeLDate => u32::read_be(bytes) as u64,
+        LeLong | LeDate | LeLDate => u32::read_le(bytes) as u64,
+        MeLong | MeDate | MeLDate => u32::read_me(bytes) as u64,
+        BeId3 => synchsafe(u32::read_be(bytes)) as u64,
+        LeId3 => synchsafe(u32::read_le(bytes)) as u64,
+        Quad | QDate | QLDate | QwDate => u64::read_ne(bytes),
+        BeQuad | BeQDate | BeQLDate | BeQwDate => u64::read_be(bytes),
+        LeQuad | LeQDate | LeQLDate | LeQwDate => u64::read_le(bytes),
+        _ => unreachable!("{:?} is not a numeric type", vtype),
+    }
+}
+
+/// The number of bytes read for a floating point type.
+fn float_size(vtype: ValueType) -> Option<usize> {
+    vtype.size().filter(|_| vtype.is_float())
+}
+
+fn read_float(vtype: ValueType, bytes: &[u8]) -> f64 {
+    use ValueType::*;
+    match vtype {
+        Float => f32::read_ne(bytes) as f64,
+        BeFloat => f32::read_be(bytes) as f64,
+        LeFloat => f32::read_le(bytes) as f64,
+        Double => f64::read_ne(bytes),
+        BeDouble => f64::read_be(bytes),
+        LeDouble => f64::read_le(bytes),
+        _ => unreachable!("{:?} is not a floating point type", vtype),
+    }
+}
+
+/// Apply the mask of a floating point test. Like libmagic only the
+/// arithmetic operators are supported, with the mask converted to the
+/// precision of the type.
+fn apply_float_mask(m: &Magic, value: f64) -> f64 {
+    let ValueOption::Numeric { mask } = m.value_options else {
+        return value;
+    };
+    if mask == 0 {
+        return value;
+    }
+
+    let single = float_size(m.value_type) == Some(4);
+    let mask = if single {
+        mask as f32 as f64
+    } else {
+        mask as f64
+    };
+    let value = match m.mask_operation.op {
+        IndirectionOperator::Add => value + mask,
+        IndirectionOperator::Subtract => value - mask,
+        IndirectionOperator::Multiply => value * mask,
+        IndirectionOperator::Divide => value / mask,
+        _ => value,
+    };
+
+    // Keep the result at the precision libmagic computes it in.
+    if single {
+        value as f32 as f64
+    } else {
+        value
+    }
+}
+
+/// Compare a floating point value against the value of `m`. Equality is
+/// tested to within the machine epsilon of the type, so NaN never equals
+/// anything, and the bitwise relations never match.
+fn check_float(m: &Magic, value: f64) -> bool {
+    use crate::value::NumericValue;
+
+    let (expected, epsilon) = match m.numeric_value() {
+        Some(NumericValue::F32(v)) => (v as f64, f32::EPSILON as f64),
+        Some(NumericValue::F64(v)) => (v, f64::EPSILON),
+        _ => return false,
+    };
+    let equal = (value - expected).abs() < epsilon;
+
+    match m.relation {
+        Relation::Anything => true,
+        Relation::Equal => equal,
+        Relation::NotEqual => !equal,
+        Relation::Greater => value > expected,
+        Relation::Lesser => value < expected,
+        Relation::BitAnd | Relation::BitXor => false,
+    }
+}
+
+/// Read a variable length integer made up of 7-bit groups, where the top
+/// bit of each byte marks that another one follows, returning its value
+/// along with the number of bytes it takes up. `BeVarInt` stores the most
+/// significant group first and `LeVarInt` the least significant one.
+fn read_varint(vtype: ValueType, bytes: &[u8]) -> Option<(u64, usize)> {
+    let len = bytes.iter().position(|b| b & 0x80 == 0)? + 1;
+    let groups = bytes[..len].iter().map(|b| (b & 0x7f) as u64);
+
+    let value = if matches!(vtype, ValueType::LeVarInt) {
+        groups.enumerate().fold(0, |value, (idx, group)| {
+            value | group.checked_shl(7 * idx as u32).unwrap_or(0)
+        })
+    } else {
+        groups.fold(0, |value, group| value << 7 | group)
+    };
+    Some((value, len))
+}
+
+/// Decode an ID3v2 synchsafe integer, which only uses the low seven bits of
+/// each byte so that it can't be mistaken for an MPEG frame sync.
+fn synchsafe(value: u32) -> u32 {
+    (value & 0x7f)
+        | (value >> 8 & 0x7f) << 7
+        | (value >> 16 & 0x7f) << 14
+        | (value >> 24 & 0x7f) << 21
+}
+
+/// Format the value of a date type the way libmagic does, or return `None`
+/// for the other numeric types.
+fn format_date(vtype: ValueType, value: u64) -> Option<String> {
+    use ValueType::*;
+
+    // 32-bit dates are always treated as signed.
+    let date = match vtype {
+        Date | BeDate | LeDate | MeDate => {
+            date::format_datetime(value as u32 as i32 as i64, Clock::Utc)
+        }
+        LDate | BeLDate | LeLDate | MeLDate => {
+            date::format_datetime(value as u32 as i32 as i64, Clock::Local)
+        }
+        QDate | BeQDate | LeQDate => {
+            date::format_datetime(value as i64, Clock::Utc)
+        }
+        QLDate | BeQLDate | LeQLDate => {
+            date::format_datetime(value as i64, Clock::Local)
+        }
+        QwDate | BeQwDate | LeQwDate => {
+            date::format_datetime(value as i64, Clock::Windows)
+        }
+        MSDosDate | LeMSDosDate | BeMsDosDate => {
+            date::format_dos_date(value as u16)
+        }
+        MSDosTime | LeMSDOSTime | BeMSDOSTime => {
+            date::format_dos_time(value as u16)
+        }
+        _ => return None,
+    };
+    Some(date)
+}
+
+fn width_mask(vtype: ValueType) -> u64 {
+    match type_size(vtype) {
+        Some(8) | None => u64::MAX,
+        Some(size) => (1u64 << (size * 8)) - 1,
+    }
+}
+
+fn apply_mask(m: &Magic, value: u64) -> Result<u64> {
+    let width = width_mask(m.value_type);
+    let mut value = value & width;
+
+    if let ValueOption::Numeric { mask } = m.value_options {
+        if mask != 0 {
+            let mask = mask & width;
+            value = match m.mask_operation.op {
+                IndirectionOperator::And => value & mask,
+                IndirectionOperator::Or => value | mask,
+                IndirectionOperator::Xor => value ^ mask,
+                IndirectionOperator::Add => value.wrapping_add(mask),
+                IndirectionOperator::Subtract => value.wrapping_sub(mask),
+                IndirectionOperator::Multiply => value.wrapping_mul(mask),
+                IndirectionOperator::Divide | IndirectionOperator::Modulo
+                    if mask == 0 =>
+                {
+                    return Err(MatchError::DivideByZero(m.line_number));
+                }
+                IndirectionOperator::Divide => value / mask,
+                IndirectionOperator::Modulo => value % mask,
+            };
+        }
+    }
+
+    if m.mask_operation.flags.inverse {
+        value = !value;
+    }
+
+    Ok(value & width)
+}
+
+fn sign_extend(vtype: ValueType, value: u64) -> u64 {
+    match type_size(vtype) {
+        Some(1) => value as i8 as i64 as u64,
+        Some(2) => value as i16 as i64 as u64,
+        Some(4) => value as i32 as i64 as u64,
+        _ => value,
+    }
+}
+
+/// Find the first of the `range` offsets at the start of `data` at which
+/// `pattern` matches with the modifiers in `flags`. Plain patterns are
+/// found with a substring search, otherwise only the offsets at which the
+/// first byte could match are compared in full.
+fn search(
+    pattern: &[u8],
+    data: &[u8],
+    range: usize,
+    flags: StringFlags,
+) -> Option<usize> {
+    let last = data
+        .len()
+        .checked_sub(pattern.len())?
+        .min(range.checked_sub(1)?);
+
+    let whitespace =
+        flags.is_compact_whitespace() || flags.is_optional_whitespace();
+    let case = flags.is_ignore_lowercase() || flags.is_ignore_uppercase();
+    if !whitespace && !case && !flags.is_full_word() {
+        return memmem::find(&data[..last + pattern.len()], pattern);
+    }
+
+    let Some(&first) = pattern.first() else {
+        return Some(0);
+    };
+    let mut idx = 0;
+    while idx <= last {
+        let candidates = &data[idx..=last];
+        idx += if whitespace && is_space(first) {
+            0
+        } else if flags.is_ignore_lowercase() && first.is_ascii_lowercase() {
+            memchr::memchr2(first, first.to_ascii_uppercase(), candidates)?
+        } else if flags.is_ignore_uppercase() && first.is_ascii_uppercase() {
+            memchr::memchr2(first, first.to_ascii_lowercase(), candidates)?
+        } else {
+            memchr::memchr(first, candidates)?
+        };
+
+        if strncmp(pattern, &data[idx..], data.len() - idx, flags) == 0 {
+            return Some(idx);
+        }
+        idx += 1;
+    }
+    None
+}
+
+/// The data a `regex` test is applied to at the start of `data`, which is
+/// the number of lines or bytes given by the range of the test, or all of
+/// it without a range, but never more than `max` bytes.
+fn regex_window<'a>(m: &Magic, data: &'a [u8], max: usize) -> &'a [u8] {
+    let range = m.string_range() as usize;
+    let (lines, bytes) = if m.string_flags().is_regex_line_count() {
+        // libmagic assumes lines are at most 80 bytes long.
+        (range, range.saturating_mul(80))
+    } else {
+        (0, range)
+    };
+    let bytes = match bytes {
+        0 => data.len(),
+        bytes => bytes.min(data.len()),
+    };
+    let window = &data[..bytes.min(max)];
+
+    let mut end = window.len();
+    if lines > 0 {
+        let mut pos = 0;
+        let mut last = 0;
+        let mut remaining = lines;
+        while remaining > 0 && pos < window.len() {
+            let rest = &window[pos..];
+            let Some(eol) = memchr::memchr(b'\n', rest)
+                .or_else(|| memchr::memchr(b'\r', rest))
+            else {
+                break;
+            };
+            pos += eol;
+            if window[pos] == b'\r' && window.get(pos + 1) == Some(&b'\n') {
+                pos += 1;
+            }
+            // Like libmagic the window ends at the final line terminator
+            // itself, not after it.
+            last = pos;
+            pos += 1;
+            remaining -= 1;
+        }
+        if remaining == 0 {
+            end = last;
+        }
+    }
+
+    // libmagic overwrites the last byte with a null byte and, as the data
+    // is handed to regexec(3) as a C string, stops at the first null byte.
+    // With a line count that byte is the end of the last line, or the '\r'
+    // of a "\r\n" terminator.
+    let window = &window[..end.saturating_sub(1)];
+    let nul = memchr::memchr(0, window).unwrap_or(window.len());
+    &window[..nul]
+}
+
+/// `isspace(3)` in the C locale, which unlike `u8::is_ascii_whitespace`
+/// includes vertical tab.
+fn is_space(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r')
+}
+
+/// Compare `pattern` against the start of `data` like libmagic's
+/// `file_strncmp`, returning the difference of the first mismatched bytes,
+/// or 1 if more than `max` bytes of data would be needed to skip
+/// whitespace or the match isn't followed by a word boundary when one is
+/// required.
+fn strncmp(pattern: &[u8], data: &[u8], max: usize, flags: StringFlags) -> i64 {
+    // libmagic compares against a null padded copy of the data.
+    let at = |idx: usize| data.get(idx).copied().unwrap_or(0);
+    let pattern_at = |idx: usize| pattern.get(idx).copied().unwrap_or(0);
+
+    // Whitespace may make the data consumed longer than the pattern.
+    let end = if flags.is_compact_whitespace() || flags.is_optional_whitespace()
+    {
+        max
+    } else {
+        pattern.len()
+    };
+
+    let mut b = 0;
+    for (idx, &a) in pattern.iter().enumerate() {
+        if b >= end {
+            return 1;
+        }
+
+        let diff = if flags.is_ignore_lowercase() && a.is_ascii_lowercase() {
+            at(b).to_ascii_lowercase() as i64 - a as i64
+        } else if flags.is_ignore_uppercase() && a.is_ascii_uppercase() {
+            at(b).to_ascii_uppercase() as i64 - a as i64
+        } else if flags.is_compact_whitespace() && is_space(a) {
+            if !is_space(at(b)) {
+                return 1;
+            }
+            b += 1;
+            if !is_space(pattern_at(idx + 1)) {
+                while b < end && is_space(at(b)) {
+                    b += 1;
+                }
+            }
+            continue;
+        } else if flags.is_optional_whitespace() && is_space(a) {
+            while b < end && is_space(at(b)) {
+                b += 1;
+            }
+            continue;
+        } else {
+            at(b) as i64 - a as i64
+        };
+
+        if diff != 0 {
+            return diff;
+        }
+        b += 1;
+    }
+
+    if flags.is_full_word() && at(b) != 0 && !is_space(at(b)) {
+        return 1;
+    }
+    0
+}
+
+/// Extract the string of a `pstring` test from `bytes`, which start with
+/// its length prefix, returning `None` if the length is invalid. Like
+/// libmagic the string is read from at most `MAX_STRING + 1` null padded
+/// bytes.
+fn pstring(flags: StringFlags, bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut padded = [0u8; MAX_STRING + 1];
+    let copied = bytes.len().min(padded.len());
+    padded[..copied].copy_from_slice(&bytes[..copied]);
+
+    let size = flags.pstring_length_size();
+    let mut len = match (size, flags.is_pstring_length_le()) {
+        (1, _) => padded[0] as usize,
+        (2, false) => u16::read_be(&padded) as usize,
+        (2, true) => u16::read_le(&padded) as usize,
+        (_, false) => u32::read_be(&padded) as usize,
+        (_, true) => u32::read_le(&padded) as usize,
+    };
+    if flags.is_pstring_length_includes_itself() {
+        len = len.checked_sub(size)?;
+    }
+
+    let len = len.min(padded.len() - size);
+    Some(padded[size..size + len].to_vec())
+}
+
+/// Narrow the UTF-16 data of a 16-bit string test to the bytes the ASCII
+/// pattern is compared against by keeping the low byte of each code unit.
+/// Like libmagic a null low byte only ends the string when the high byte
+/// is null as well, otherwise it is replaced by a space.
+fn string16(bytes: &[u8], big_endian: bool) -> Vec<u8> {
+    bytes
+        .chunks(2)
+        .take(MAX_STRING)
+        .map_while(|unit| {
+            let (low, high) = match (big_endian, unit) {
+                (true, [high, low]) => (Some(*low), *high),
+                (false, [low, high]) => (Some(*low), *high),
+                // A trailing odd byte is the low byte of a little endian
+                // code unit only.
+                (false, [low]) => (Some(*low), 0),
+                _ => (None, 0),
+            };
+            match low? {
+                0 if high != 0 => Some(b' '),
+                low => Some(low),
+            }
+        })
+        .collect()
+}
+
+/// The string data matched by a non-equality string test as it should be
+/// displayed.
+fn string_data(m: &Magic, bytes: &[u8]) -> Vec<u8> {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    let mut bytes = &bytes[..end];
+
+    // An empty pattern (i.e., `string x`) displays a single line.
+    if m.value_len == 0 {
+        let end = bytes
+            .iter()
+            .position(|b| *b == b'\r' || *b == b'\n')
+            .unwrap_or(bytes.len());
+        bytes = &bytes[..end];
+    }
+
+    bytes.to_vec()
+}
+
+/// Strip leading and trailing whitespace for the `T` modifier.
+fn trim(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| !is_space(*b));
+    let end = bytes.iter().rposition(|b| !is_space(*b));
+    match (start, end) {
+        (Some(start), Some(end)) => &bytes[start..=end],
+        _ => &[],
+    }
+}
+
+/// Render bytes for display as is, up to the first null byte.
+fn raw(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Render bytes for display, escaping anything that isn't printable ASCII as
+/// a three digit octal escape. Stops at the first null byte.
+fn printable(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    for b in bytes.iter().take_while(|b| **b != 0) {
+        if (0x20..0x7f).contains(b) {
+            out.push(*b as char);
+        } else {
+            out.push_str(&format!("\\{:03o}", b));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::magic::tests::{entry, string_entry};
+    use crate::magic::MagicFlags;
+    use crate::magic_set::Limits;
+    use crate::structs::MagicMap;
+    use Relation::*;
+    use ValueType::*;
+
+    fn set(entries: Vec<Magic>) -> MagicSet {
+        MagicSet::new(MagicMap::new(entries, Vec::new()))
+    }
+
+    fn run(entries: Vec<Magic>, buf: &[u8]) -> Option<std::string::String> {
+        softmagic(
+            &set(entries),
+            MagicSetFlags::default(),
+            buf,
+            TestType::Binary,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn check_numeric_tests() {
+        // Values are stored sign extended to 64 bits by the compiler.
+        let java = (0xcafebabeu32 as i32 as i64).to_le_bytes();
+        let entries = || {
+            vec![
+                entry(0, 0, BeLong, Equal, &java, "Java"),
+                entry(0, 0, LeShort, Equal, &0x5a4du16.to_le_bytes(), "MZ"),
+                entry(1, 2, Byte, Greater, &[1], "version %d"),
+                entry(1, 2, Byte, Anything, &[0], "[%#x]"),
+            ]
+        };
+
+        let buf = [0xca, 0xfe, 0xba, 0xbe];
+        assert_eq!(run(entries(), &buf).as_deref(), Some("Java"));
+
+        let buf = [b'M', b'Z', 0x02, 0x00];
+        assert_eq!(run(entries(), &buf).as_deref(), Some("MZ version 2 [0x2]"));
+
+        // Bytes are signed unless the entry is marked unsigned.
+        let buf = [b'M', b'Z', 0x90, 0x00];
+        assert_eq!(run(entries(), &buf).as_deref(), Some("MZ [0x90]"));
+
+        assert_eq!(run(entries(), &[0, 0]), None);
+    }
+
+    #[test]
+    fn check_signed_comparisons() {
+        // -1 is stored sign extended to 64 bits.
+        let minus_one = (-1i64).to_le_bytes();
+        let entries = vec![entry(0, 0, Byte, Lesser, &[0], "negative")];
+        assert_eq!(run(entries, &[0xff]).as_deref(), Some("negative"));
+        let entries = vec![entry(0, 0, Byte, Equal, &minus_one, "minus one")];
+        assert_eq!(run(entries, &[0xff]).as_deref(), Some("minus one"));
+
+        let mut unsigned = entry(0, 0, Byte, Greater, &[0x7f], "big");
+        unsigned.flags =
+            MagicFlags::from(MagicFlags::BIN_TEST | MagicFlags::UNSIGNED);
+        assert_eq!(run(vec![unsigned], &[0xff]).as_deref(), Some("big"));
+    }
+
+    #[test]
+    fn check_masks() {
+        let mut masked = entry(0, 0, BeShort, Equal, &[0x00, 0x10], "masked");
+        masked.value_options = ValueOption::Numeric { mask: 0xff00 };
+        assert_eq!(run(vec![masked], &[0x10, 0x42]).as_deref(), Some("masked"));
+
+        let mut zero = entry(0, 0, Byte, Equal, &[0], "zero");
+        zero.value_options = ValueOption::Numeric { mask: 0x100 };
+        zero.mask_operation.op = IndirectionOperator::Divide;
+        assert!(matches!(
+            softmagic(
+                &set(vec![zero]),
+                MagicSetFlags::default(),
+                &[1],
+                TestType::Binary
+            ),
+            Err(MatchError::DivideByZero(_))
+        ));
+    }
+
+    #[test]
+    fn check_strings() {
+        let entries = vec![
+            entry(0, 0, String, Equal, b"#!", "script"),
+            entry(1, 2, String, Anything, b"", "for %s"),
+        ];
+        let buf = b"#!/bin/sh\nexit 0\n";
+        assert_eq!(run(entries, buf).as_deref(), Some("script for /bin/sh"));
+
+        let entries = vec![entry(0, 0, String, Equal, b"\x89PNG", "%s image")];
+        let buf = b"\x89PNG\r\n";
+        assert_eq!(run(entries, buf).as_deref(), Some("\\211PNG image"));
+    }
+
+    #[test]
+    fn check_default_and_clear() {
+        let entries = || {
+            vec![
+                entry(0, 0, Byte, Equal, &[1], "type"),
+                entry(1, 1, Byte, Equal, &[1], "one"),
+                entry(1, 1, Byte, Equal, &[2], "two"),
+                entry(1, 0, Default, Anything, &[], "unknown"),
+                entry(1, 0, Clear, Anything, &[], ""),
+                entry(1, 0, Default, Anything, &[], "always"),
+            ]
+        };
+
+        assert_eq!(run(entries(), &[1, 2]).as_deref(), Some("type two always"));
+        assert_eq!(
+            run(entries(), &[1, 3]).as_deref(),
+            Some("type unknown always")
+        );
+    }
+
+    #[test]
+    fn check_no_space_and_levels() {
+        let mut minor = entry(2, 2, Byte, Anything, &[], ".%d");
+        minor.flags = MagicFlags::from(MagicFlags::NO_SPACE);
+        let entries = vec![
+            entry(0, 0, Byte, Equal, &[1], "format"),
+            entry(1, 1, Byte, Equal, &[9], "nope"),
+            entry(2, 2, Byte, Anything, &[], "skipped"),
+            entry(1, 1, Byte, Anything, &[], "v%d"),
+            minor,
+        ];
+
+        assert_eq!(run(entries, &[1, 2, 3]).as_deref(), Some("format v2.3"));
+    }
+
+    #[test]
+    fn check_continue() {
+        let mut set = set(vec![
+            entry(0, 0, Byte, Equal, &[1], "first"),
+            entry(1, 1, Byte, Anything, &[], "v%d"),
+            entry(0, 0, Byte, Anything, &[], "second"),
+            entry(0, 0, Byte, Equal, &[2], "third"),
+        ]);
+        set.set_flags(MagicSetFlags::CONTINUE | MagicSetFlags::RAW);
+
+        let desc =
+            softmagic(&set, set.flags(), &[1, 2], TestType::Binary).unwrap();
+        assert_eq!(desc.as_deref(), Some("first v2\n- second"));
+    }
+
+    #[test]
+    fn check_mime_from_continuation() {
+        let mut gif = entry(1, 3, String, Equal, b"89a", "version 89a");
+        gif.mimetype = "image/gif".to_string();
+        let mut set = set(vec![
+            entry(0, 0, String, Equal, b"GIF", "GIF image data,"),
+            entry(1, 3, String, Equal, b"87a", "version 87a"),
+            gif,
+        ]);
+
+        assert_eq!(
+            set.buffer(b"GIF89a").unwrap(),
+            "GIF image data, version 89a"
+        );
+
+        set.set_flags(MagicSetFlags::MIME_TYPE);
+        assert_eq!(set.buffer(b"GIF89a").unwrap(), "image/gif");
+        assert_eq!(
+            set.buffer(b"GIF87a\0\0").unwrap(),
+            "application/octet-stream"
+        );
+    }
+
+    #[test]
+    fn check_not_equal_out_of_bounds() {
+        let entries = vec![
+            entry(0, 0, Byte, Equal, &[1], "short"),
+            entry(1, 8, LeLong, NotEqual, &[0], "truncated"),
+        ];
+        assert_eq!(run(entries, &[1]).as_deref(), Some("short truncated"));
+
+        // The end of the value lies past the buffer, which only ends this
+        // continuation: the entry still counts as a match.
+        let entries = vec![
+            entry(0, 0, Byte, Equal, &[1], "short"),
+            entry(1, 8, LeLong, NotEqual, &[0], "truncated"),
+            entry(2, 0, Byte, Anything, &[0], "deeper"),
+            entry(1, 1, Byte, Equal, &[2], "sibling"),
+            entry(0, 0, Byte, Anything, &[0], "second"),
+        ];
+        assert_eq!(
+            run(entries, &[1, 2]).as_deref(),
+            Some("short truncated sibling")
+        );
+    }
+
+    #[test]
+    fn check_indirect_offsets() {
+        let indirect = |offset, in_type, in_offset, op, desc: &str| {
+            let mut m = entry(1, offset, String, Equal, b"PE", desc);
+            m.flags = MagicFlags::from(MagicFlags::INDIRECT);
+            m.indirection_type = in_type;
+            m.indirection_offset = in_offset;
+            m.indirection_operation.op = op;
+            m
+        };
+
+        let mut nested =
+            indirect(5, Byte, 1, IndirectionOperator::Multiply, "");
+        nested.indirection_operation.flags.indirect = true;
+        nested.desc = "nested".to_string();
+        let entries = vec![
+            entry(0, 0, Byte, Equal, &[8], "pointer"),
+            indirect(0, LeLong, 0, IndirectionOperator::And, "direct"),
+            indirect(4, Byte, 2, IndirectionOperator::Add, "added"),
+            nested,
+            indirect(4, Byte, 100, IndirectionOperator::Subtract, "negative"),
+            indirect(60, LeLong, 0, IndirectionOperator::And, "outside"),
+        ];
+        let buf = b"\x08\0\0\0\x06\x02\x04\0PE\0\0";
+        assert_eq!(
+            run(entries, buf).as_deref(),
+            Some("pointer direct added nested")
+        );
+    }
+
+    #[test]
+    fn check_indirect_type() {
+        let mut inner = entry(1, 0, Indirect, Anything, &[0], "at %u: ");
+        inner.flags = MagicFlags::from(MagicFlags::INDIRECT);
+        inner.indirection_type = LeLong;
+        let entries = vec![
+            entry(0, 0, String, Equal, b"PE", "PE executable"),
+            entry(0, 0, LeLong, Equal, &[8], "pointer"),
+            inner,
+        ];
+        let buf = b"\x08\0\0\0\0\0\0\0PE\0\0";
+        assert_eq!(
+            run(entries, buf).as_deref(),
+            Some("pointer at 8: PE executable")
+        );
+
+        // Each level of indirection starts one byte further into the buffer.
+        let entries = vec![entry(0, 1, Indirect, Anything, &[0], "")];
+        let result = softmagic(
+            &set(entries),
+            MagicSetFlags::default(),
+            &[0; 64],
+            TestType::Binary,
+        );
+        assert!(matches!(result, Err(MatchError::IndirectionLimit(50))));
+    }
+
+    #[test]
+    fn check_relative_indirect() {
+        let run_relative = |flags| {
+            let mut inner =
+                string_entry(Indirect, Anything, &[0], "at %u: ", 0, flags);
+            inner.cont_level = 1;
+            inner.offset = 1;
+            let entries = vec![
+                entry(0, 0, String, Equal, b"PE", "PE executable"),
+                entry(0, 0, Byte, Equal, &[1], "one"),
+                entry(1, 2, Use, Equal, b"inner", ""),
+            ];
+            let names = vec![entry(0, 0, Name, Equal, b"inner", ""), inner];
+            let set = MagicSet::new(MagicMap::new(entries, names));
+            let buf = b"\x01\0\0PE\0\0";
+            softmagic(&set, MagicSetFlags::default(), buf, TestType::Binary)
+                .unwrap()
+        };
+
+        // With `r` the offset is relative to the `use` entry at 2.
+        assert_eq!(
+            run_relative(StringFlags::INDIRECT_RELATIVE).as_deref(),
+            Some("one at 3: PE executable")
+        );
+        assert_eq!(run_relative(0).as_deref(), Some("one"));
+    }
+
+    #[test]
+    fn check_relative_offsets() {
+        let relative = |cont_level, offset, value_type, value: &[u8], desc| {
+            let mut m =
+                entry(cont_level, offset, value_type, Equal, value, desc);
+            m.flags = MagicFlags::from(MagicFlags::OFFSET_ADD);
+            m
+        };
+        let buf = b"\x04abcXYZend";
+
+        let entries = vec![
+            entry(0, 0, Byte, Equal, &[4], "length"),
+            relative(1, 0, String, b"abc", "abc"),
+            relative(2, 0, String, b"XYZ", "xyz"),
+            relative(1, -1, Byte, &[4], "again"),
+        ];
+        assert_eq!(run(entries, buf).as_deref(), Some("length abc xyz again"));
+
+        // Offsets from the end of the file, which continuations are then
+        // relative to.
+        let mut end = entry(0, 3, String, Equal, b"end", "end");
+        end.flags = MagicFlags::from(
+            MagicFlags::BIN_TEST | MagicFlags::OFFSET_NEGATIVE,
+        );
+        let entries = vec![end, entry(1, 1, String, Equal, b"nd", "nd")];
+        assert_eq!(run(entries, buf).as_deref(), Some("end nd"));
+
+        // A continuation before the start of the buffer doesn't stop its
+        // siblings from being tested.
+        let mut before = entry(1, 100, Byte, Anything, &[0], "before");
+        before.flags = MagicFlags::from(MagicFlags::OFFSET_NEGATIVE);
+        let entries = vec![
+            entry(0, 0, Byte, Equal, &[1], "one"),
+            before,
+            entry(1, 1, Byte, Equal, &[2], "next"),
+        ];
+        assert_eq!(run(entries, &[1, 2]).as_deref(), Some("one next"));
+
+        // The result of an indirection relative to the previous level.
+        let mut indirect = entry(1, 0, Byte, Equal, b"Y", "Y");
+        indirect.flags = MagicFlags::from(
+            MagicFlags::INDIRECT | MagicFlags::INDIRECT_OFFSET_ADD,
+        );
+        indirect.indirection_type = Byte;
+        let entries = vec![entry(0, 0, Byte, Equal, &[4], "length"), indirect];
+        assert_eq!(run(entries, buf).as_deref(), Some("length Y"));
+
+        // A negative indirection wraps around before the previous level's
+        // offset is added, as `&(3.b-1)` does after a two byte match.
+        let mut wrapped = entry(1, 3, Byte, Equal, b"B", "wrapped");
+        wrapped.flags = MagicFlags::from(
+            MagicFlags::INDIRECT | MagicFlags::INDIRECT_OFFSET_ADD,
+        );
+        wrapped.indirection_type = Byte;
+        wrapped.indirection_offset = 1;
+        wrapped.indirection_operation.op = IndirectionOperator::Subtract;
+        let entries = vec![entry(0, 0, String, Equal, b"AB", "AB"), wrapped];
+        assert_eq!(run(entries, b"AB\0\0").as_deref(), Some("AB wrapped"));
+    }
+
+    #[test]
+    fn check_conditionals() {
+        let branch = |conditional_type, relation, value, desc| {
+            let mut m = entry(1, 1, Byte, relation, &[value], desc);
+            m.conditional_type = conditional_type;
+            m
+        };
+        let entries = || {
+            vec![
+                entry(0, 0, Byte, Equal, &[1], "one"),
+                branch(ConditionalType::If, Equal, 2, "two"),
+                branch(ConditionalType::Elif, Equal, 3, "three"),
+                branch(ConditionalType::Elif, Greater, 1, "more"),
+                branch(ConditionalType::Else, Anything, 0, "other"),
+            ]
+        };
+
+        assert_eq!(run(entries(), &[1, 2]).as_deref(), Some("one two"));
+        assert_eq!(run(entries(), &[1, 3]).as_deref(), Some("one three"));
+        assert_eq!(run(entries(), &[1, 9]).as_deref(), Some("one more"));
+        assert_eq!(run(entries(), &[1, 0]).as_deref(), Some("one other"));
+
+        // An `if` whose data is out of range isn't tested at all, so the
+        // `else` after it follows the test before it on the same level.
+        let mut oob = entry(1, 50, Byte, Equal, &[2], "oob");
+        oob.conditional_type = ConditionalType::If;
+        let entries = vec![
+            entry(0, 0, Byte, Equal, &[1], "one"),
+            entry(1, 1, Byte, Equal, &[2], "plain"),
+            oob,
+            branch(ConditionalType::Else, Anything, 0, "else"),
+        ];
+        assert_eq!(run(entries, &[1, 2]).as_deref(), Some("one plain"));
+    }
+
+    #[test]
+    fn check_named_subroutines() {
+        let names = || {
+            vec![
+                entry(0, 0, Name, Equal, b"pair", ""),
+                entry(1, 0, BeShort, Equal, &0x1234u16.to_le_bytes(), "big"),
+                entry(1, 0, BeShort, Equal, &0x3412u16.to_le_bytes(), "little"),
+                entry(1, 2, Byte, Anything, &[0], "(%d)"),
+                entry(0, 0, Name, Equal, b"loop", ""),
+                entry(1, 0, Use, Equal, b"loop", ""),
+            ]
+        };
+        let run_named = |name: &[u8]| {
+            let entries = vec![
+                entry(0, 0, Byte, Equal, &[1], "one"),
+                entry(1, 1, Use, Equal, name, ""),
+            ];
+            let set = MagicSet::new(MagicMap::new(entries, names()));
+            softmagic(
+                &set,
+                MagicSetFlags::default(),
+                &[1, 0x12, 0x34, 7],
+                TestType::Binary,
+            )
+        };
+
+        assert_eq!(run_named(b"pair").unwrap().as_deref(), Some("one big (7)"));
+        assert_eq!(
+            run_named(b"^pair").unwrap().as_deref(),
+            Some("one little (7)")
+        );
+        assert!(matches!(
+            run_named(b"missing"),
+            Err(MatchError::UnknownName(name)) if name == "missing"
+        ));
+        assert!(matches!(run_named(b"loop"), Err(MatchError::NameLimit(50))));
+    }
+
+    #[test]
+    fn check_strength_order() {
+        let entries = vec![
+            entry(0, 0, Byte, Anything, &[0], "anything"),
+            entry(1, 0, Byte, Anything, &[0], "(%d)"),
+            entry(0, 0, String, Equal, b"MZ", "MZ"),
+            entry(0, 0, Byte, Equal, b"M", "M"),
+        ];
+        assert_eq!(run(entries, b"MZ").as_deref(), Some("MZ"));
+
+        let entries = vec![
+            entry(0, 0, Byte, Anything, &[0], "anything"),
+            entry(1, 0, Byte, Anything, &[0], "(%d)"),
+            entry(0, 0, String, Equal, b"PE", "PE"),
+        ];
+        assert_eq!(run(entries, b"MZ").as_deref(), Some("anything (77)"));
+    }
+
+    #[test]
+    fn check_byte_orders() {
+        let value = 0x12345678u64.to_le_bytes();
+        let entries = || {
+            vec![
+                entry(0, 0, BeLong, Equal, &value, "big"),
+                entry(0, 0, LeLong, Equal, &value, "little"),
+                entry(0, 0, MeLong, Equal, &value, "middle"),
+            ]
+        };
+        let run = |buf: &[u8]| run(entries(), buf);
+        assert_eq!(run(&[0x12, 0x34, 0x56, 0x78]).as_deref(), Some("big"));
+        assert_eq!(run(&[0x78, 0x56, 0x34, 0x12]).as_deref(), Some("little"));
+        assert_eq!(run(&[0x34, 0x12, 0x78, 0x56]).as_deref(), Some("middle"));
+    }
+
+    #[test]
+    fn check_dates() {
+        let entries = vec![
+            entry(0, 0, LeDate, Anything, &[0], "created %s"),
+            entry(1, 4, LeMSDosDate, Anything, &[0], "on %s"),
+            entry(1, 6, LeMSDOSTime, Anything, &[0], "at %s"),
+            entry(1, 8, BeQwDate, Anything, &[0], "modified %s"),
+        ];
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&1700000000u32.to_le_bytes());
+        buf.extend_from_slice(&((43u16 << 9) | (11 << 5) | 14).to_le_bytes());
+        buf.extend_from_slice(&((13u16 << 11) | (45 << 5) | 15).to_le_bytes());
+        buf.extend_from_slice(&116444736000000000u64.to_be_bytes());
+        assert_eq!(
+            run(entries, &buf).as_deref(),
+            Some(
+                "created Tue Nov 14 22:13:20 2023 on Sun, Nov 14 2023 \
+                 at 13:45:30 modified Thu Jan  1 00:00:00 1970"
+            )
+        );
+    }
+
+    #[test]
+    fn check_floats() {
+        let entries = || {
+            vec![
+                entry(0, 0, BeFloat, Equal, &1.5f32.to_le_bytes(), "one"),
+                entry(1, 4, LeDouble, Greater, &0f64.to_le_bytes(), "%g"),
+                entry(1, 4, LeDouble, Lesser, &0f64.to_le_bytes(), "negative"),
+                entry(1, 12, Byte, Anything, &[0], "end %d"),
+            ]
+        };
+
+        let mut buf = 1.5f32.to_be_bytes().to_vec();
+        buf.extend_from_slice(&2.5e-7f64.to_le_bytes());
+        buf.push(7);
+        assert_eq!(run(entries(), &buf).as_deref(), Some("one 2.5e-07 end 7"));
+
+        // Doubles are compared to within their own epsilon.
+        let mut buf = 1.5f32.to_be_bytes().to_vec();
+        buf.extend_from_slice(&(-1e-300f64).to_le_bytes());
+        buf.push(0);
+        assert_eq!(run(entries(), &buf).as_deref(), Some("one negative end 0"));
+
+        let buf = (1.5f32 + f32::EPSILON).to_be_bytes();
+        assert_eq!(run(entries(), &buf).as_deref(), None);
+
+        let entries = vec![entry(0, 0, Float, NotEqual, &[0; 4], "not zero")];
+        let buf = f32::NAN.to_ne_bytes();
+        assert_eq!(run(entries, &buf).as_deref(), Some("not zero"));
+    }
+
+    #[test]
+    fn check_string_modifiers() {
+        let matches = |pattern: &[u8], flags: u32, buf: &[u8]| {
+            let m = string_entry(String, Equal, pattern, "match", 0, flags);
+            run(vec![m], buf).is_some()
+        };
+
+        // c and C
+        assert!(matches(b"<html", StringFlags::IGNORE_LOWERCASE, b"<HTML>"));
+        assert!(!matches(b"<HTML", StringFlags::IGNORE_LOWERCASE, b"<html>"));
+        assert!(matches(b"<HTML", StringFlags::IGNORE_UPPERCASE, b"<html>"));
+
+        // W needs at least one whitespace, w doesn't.
+        assert!(matches(b"a b", StringFlags::COMPACT_WHITESPACE, b"a \t b"));
+        assert!(!matches(b"a b", StringFlags::COMPACT_WHITESPACE, b"ab"));
+        assert!(matches(b"a b", StringFlags::OPTIONAL_WHITESPACE, b"ab\n"));
+        assert!(matches(b"a b", StringFlags::OPTIONAL_WHITESPACE, b"a   b"));
+
+        // f
+        assert!(matches(b"foo", StringFlags::FULL_WORD, b"foo bar"));
+        assert!(matches(b"foo", StringFlags::FULL_WORD, b"foo"));
+        assert!(!matches(b"foo", StringFlags::FULL_WORD, b"foobar"));
+
+        let entries = vec![entry(0, 0, String, Lesser, b"m", "before m")];
+        assert_eq!(run(entries, b"abc").as_deref(), Some("before m"));
+        let entries = vec![entry(0, 0, String, Greater, b"m", "after m")];
+        assert_eq!(run(entries, b"abc"), None);
+
+        // T
+        let m =
+            string_entry(String, Anything, b"", "[%s]", 0, StringFlags::TRIM);
+        assert_eq!(run(vec![m], b"  hi \t\n").as_deref(), Some("[hi]"));
+    }
+
+    #[test]
+    fn check_pstrings() {
+        let entries = vec![string_entry(PString, Equal, b"abc", "short", 0, 0)];
+        assert_eq!(run(entries, b"\x03abcd").as_deref(), Some("short"));
+
+        // Only the first two bytes are part of the string.
+        let entries = vec![string_entry(PString, Equal, b"abc", "short", 0, 0)];
+        assert_eq!(run(entries, b"\x02abcd"), None);
+
+        // H is a big endian 2 byte length and J includes the length itself.
+        let mut relative = entry(1, 0, Byte, Anything, &[0], "then %c");
+        relative.flags = MagicFlags::from(MagicFlags::OFFSET_ADD);
+        let entries = vec![
+            string_entry(
+                PString,
+                Anything,
+                b"",
+                "name %s",
+                0,
+                StringFlags::PSTRING_2_BE
+                    | StringFlags::PSTRING_LENGTH_INCLUDES_ITSELF,
+            ),
+            relative,
+        ];
+        let buf = b"\x00\x05abcZ";
+        assert_eq!(run(entries, buf).as_deref(), Some("name abc then Z"));
+
+        // l is a little endian 4 byte length.
+        let entries = vec![string_entry(
+            PString,
+            Anything,
+            b"",
+            "%s",
+            0,
+            StringFlags::PSTRING_4_LE,
+        )];
+        let buf = b"\x02\x00\x00\x00hi!";
+        assert_eq!(run(entries, buf).as_deref(), Some("hi"));
+
+        // A length that doesn't cover itself is invalid.
+        let entries = vec![string_entry(
+            PString,
+            Anything,
+            b"",
+            "bad",
+            0,
+            StringFlags::PSTRING_LENGTH_INCLUDES_ITSELF,
+        )];
+        assert_eq!(run(entries, b"\x00abc"), None);
+    }
+
+    #[test]
+    fn check_string16() {
+        let entries = || {
+            vec![
+                entry(0, 0, LeString16, Equal, b"MZ", "le"),
+                entry(0, 0, BeString16, Equal, b"MZ", "be"),
+            ]
+        };
+        assert_eq!(run(entries(), b"M\0Z\0").as_deref(), Some("le"));
+        assert_eq!(run(entries(), b"\0M\0Z").as_deref(), Some("be"));
+        assert_eq!(run(entries(), b"MZ\0\0"), None);
+
+        // A null low byte is only the end of the string if the high byte is
+        // null too.
+        let entries = vec![entry(0, 0, LeString16, Anything, b"", "title %s")];
+        let buf = b"H\0i\0\0\x01!\0\0\0x\0";
+        assert_eq!(run(entries, buf).as_deref(), Some("title Hi !"));
+    }
+
+    #[test]
+    fn check_search() {
+        // The next level continues after the match.
+        let mut version = entry(1, 0, Byte, Anything, &[0], "version %c");
+        version.flags = MagicFlags::from(MagicFlags::OFFSET_ADD);
+        let entries =
+            vec![string_entry(Search, Equal, b"PDF-", "PDF", 16, 0), version];
+        let buf = b"junk\n%PDF-1.4\n";
+        assert_eq!(run(entries, buf).as_deref(), Some("PDF version 1"));
+
+        // The pattern has to start within the range.
+        let entries = vec![string_entry(Search, Equal, b"PDF-", "PDF", 6, 0)];
+        assert_eq!(run(entries, buf), None);
+        let entries = vec![string_entry(Search, Equal, b"PDF-", "PDF", 7, 0)];
+        assert_eq!(run(entries, buf).as_deref(), Some("PDF"));
+
+        let entries = vec![string_entry(
+            Search,
+            Equal,
+            b"<html",
+            "HTML",
+            64,
+            StringFlags::IGNORE_LOWERCASE,
+        )];
+        assert_eq!(run(entries, b"<!-- -->\n<HTML>").as_deref(), Some("HTML"));
+        let entries = vec![string_entry(
+            Search,
+            Equal,
+            b"a b",
+            "found",
+            64,
+            StringFlags::COMPACT_WHITESPACE,
+        )];
+        assert_eq!(run(entries, b"xxa \t b").as_deref(), Some("found"));
+        let entries = vec![string_entry(
+            Search,
+            Equal,
+            b"end",
+            "found",
+            0,
+            StringFlags::FULL_WORD,
+        )];
+        assert_eq!(run(entries, b"ending end.").as_deref(), None);
+        let entries = vec![string_entry(
+            Search,
+            Equal,
+            b"end",
+            "found",
+            0,
+            StringFlags::FULL_WORD,
+        )];
+        assert_eq!(run(entries, b"ending end ").as_deref(), Some("found"));
+
+        // s leaves the offset at the start of the match.
+        let mut next = entry(1, 0, String, Equal, b"ab", "start");
+        next.flags = MagicFlags::from(MagicFlags::OFFSET_ADD);
+        let entries = vec![
+            string_entry(
+                Search,
+                Equal,
+                b"ab",
+                "",
+                0,
+                StringFlags::REGEX_OFFSET_START,
+            ),
+            next,
+        ];
+        assert_eq!(run(entries, b"..ab").as_deref(), Some("start"));
+
+        // An unbounded search stops at the configured limit.
+        let mut set =
+            set(vec![string_entry(Search, Equal, b"x", "found", 0, 0)]);
+        set.set_limits(Limits {
+            search_max: 4,
+            ..Limits::default()
+        });
+        let flags = MagicSetFlags::default();
+        let matched = softmagic(&set, flags, b"....x", TestType::Binary);
+        assert_eq!(matched.unwrap(), None);
+        let matched = softmagic(&set, flags, b"...x", TestType::Binary);
+        assert_eq!(matched.unwrap().as_deref(), Some("found"));
+    }
+
+    #[test]
+    fn check_regex() {
+        let mut mode = entry(1, 1, String, Anything, b"", "mode %s");
+        mode.flags = MagicFlags::from(MagicFlags::OFFSET_ADD);
+        let entries = vec![
+            string_entry(Regex, Equal, b"^begin [0-7]+", "uuencoded", 0, 0),
+            mode,
+        ];
+        let buf = b"\nbegin 644 file.txt\n";
+        assert_eq!(
+            run(entries, buf).as_deref(),
+            Some("uuencoded mode file.txt")
+        );
+
+        // The matched text is available to the description.
+        let entries = vec![string_entry(
+            Regex,
+            Equal,
+            b"v[0-9]+\\.[0-9]+",
+            "version %s",
+            0,
+            0,
+        )];
+        let buf = b"release v1.23 final\n";
+        assert_eq!(run(entries, buf).as_deref(), Some("version v1.23"));
+
+        // Ranges in lines and bytes
+        let buf = b"line one\nline two\nmagic here\n";
+        let entries = vec![string_entry(
+            Regex,
+            Equal,
+            b"magic",
+            "found",
+            2,
+            StringFlags::REGEX_LINE_COUNT,
+        )];
+        assert_eq!(run(entries, buf), None);
+        let entries = vec![string_entry(
+            Regex,
+            Equal,
+            b"magic",
+            "found",
+            3,
+            StringFlags::REGEX_LINE_COUNT,
+        )];
+        assert_eq!(run(entries, buf).as_deref(), Some("found"));
+        // The last byte of the last line isn't part of the window.
+        let entries = vec![string_entry(
+            Regex,
+            Equal,
+            b"here",
+            "found",
+            3,
+            StringFlags::REGEX_LINE_COUNT,
+        )];
+        assert_eq!(run(entries, buf), None);
+        let entries =
+            vec![string_entry(Regex, Equal, b"magic", "found", 20, 0)];
+        assert_eq!(run(entries, buf), None);
+        let entries = vec![string_entry(
+            Regex,
+            Equal,
+            b"MAGIC",
+            "found",
+            0,
+            StringFlags::IGNORE_LOWERCASE,
+        )];
+        assert_eq!(run(entries, buf).as_deref(), Some("found"));
+
+        // The data is capped at regex_max bytes.
+        let mut limited =
+            set(vec![string_entry(Regex, Equal, b"magic", "found", 0, 0)]);
+        limited.set_limits(Limits {
+            regex_max: 16,
+            ..Limits::default()
+        });
+        let flags = MagicSetFlags::default();
+        let matched = softmagic(&limited, flags, buf, TestType::Binary);
+        assert_eq!(matched.unwrap(), None);
+
+        let invalid = set(vec![string_entry(Regex, Equal, b"(", "bad", 0, 0)]);
+        let matched = softmagic(&invalid, flags, buf, TestType::Binary);
+        assert!(matches!(matched, Err(MatchError::InvalidRegex(..))));
+    }
+
+    #[test]
+    fn check_der() {
+        // A certificate starts with a sequence containing a sequence, which
+        // starts with the optional version.
+        let mut entries = vec![
+            entry(0, 0, Der, Equal, b"seq", "DER"),
+            entry(1, 0, Der, Equal, b"seq", "TBS"),
+            // The context specific [0] only has a tag number.
+            entry(2, 0, Der, Equal, b"eoc", "v%s"),
+            entry(3, 0, Der, Equal, b"int1=x", "%s"),
+            entry(2, 0, Der, Equal, b"prt_str=x", "issued by %s"),
+        ];
+        for m in &mut entries[1..] {
+            m.flags = MagicFlags::from(MagicFlags::OFFSET_ADD);
+        }
+        let buf = b"\x30\x0f\x30\x0c\xa0\x03\x02\x01\x02\x13\x05Alice\x00\x00";
+        assert_eq!(
+            run(entries, buf).as_deref(),
+            Some("DER TBS v 02 issued by Alice")
+        );
+
+        let entries = vec![entry(0, 0, Der, Equal, b"set", "set")];
+        assert_eq!(run(entries, buf), None);
+
+        // The offset of a `der` test counts twice towards the offset of its
+        // contents.
+        let mut next = entry(1, 0, Byte, Anything, &[], "next %d");
+        next.flags = MagicFlags::from(MagicFlags::OFFSET_ADD);
+        let entries = vec![entry(0, 2, Der, Equal, b"int", "DER"), next];
+        let buf = [0, 0, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x00];
+        assert_eq!(run(entries, &buf).as_deref(), Some("DER next 6"));
+    }
+
+    #[test]
+    fn check_guids() {
+        let asf = [
+            0x30, 0x26, 0xb2, 0x75, 0x8e, 0x66, 0xcf, 0x11, 0xa6, 0xd9, 0x00,
+            0xaa, 0x00, 0x62, 0xce, 0x6c,
+        ];
+        let header = entry(0, 0, Guid, Equal, &asf, "ASF %s");
+        let mut next = entry(1, 0, Guid, Anything, &[0], "then %s");
+        next.flags = MagicFlags::from(MagicFlags::OFFSET_ADD);
+
+        let mut buf = asf.to_vec();
+        buf.extend_from_slice(&asf);
+        buf[16] = 0x31;
+        assert_eq!(
+            run(vec![header, next], &buf).as_deref(),
+            Some(
+                "ASF 75B22630-668E-11CF-A6D9-00AA0062CE6C \
+                 then 75B22631-668E-11CF-A6D9-00AA0062CE6C"
+            )
+        );
+
+        let entries = vec![entry(0, 0, Guid, Equal, &asf, "ASF")];
+        assert_eq!(run(entries, &buf[1..]), None);
+    }
+
+    #[test]
+    fn check_varints() {
+        assert_eq!(read_varint(BeVarInt, &[0x7f]), Some((0x7f, 1)));
+        assert_eq!(read_varint(BeVarInt, &[0x81, 0x00]), Some((0x80, 2)));
+        assert_eq!(
+            read_varint(LeVarInt, &[0xe5, 0x8e, 0x26]),
+            Some((624485, 3))
+        );
+        assert_eq!(read_varint(LeVarInt, &[0x80, 0x80]), None);
+
+        let mut tag = entry(1, 0, Byte, Anything, &[0], "tag %c");
+        tag.flags = MagicFlags::from(MagicFlags::OFFSET_ADD);
+        let small = 0x7fu64.to_le_bytes();
+        let entries =
+            vec![entry(0, 0, BeVarInt, Greater, &small, "size %llu"), tag];
+        assert_eq!(
+            run(entries, b"\x82\x80\x00T").as_deref(),
+            Some("size 32768 tag T")
+        );
+
+        let value = 16384u64.to_le_bytes();
+        let entries = vec![entry(0, 1, LeVarInt, Equal, &value, "le")];
+        assert_eq!(run(entries, b"\x00\x80\x80\x01").as_deref(), Some("le"));
+    }
+
+    #[test]
+    fn check_id3() {
+        assert_eq!(synchsafe(0x00000201), 0x101);
+        assert_eq!(synchsafe(0x7f7f7f7f), 0x0fffffff);
+
+        // Skip the ID3v2 tag, whose size excludes its 10 byte header, to
+        // find the MPEG frame sync.
+        let mut frame = entry(1, 6, BeShort, Equal, &[0xfb, 0xff], "MPEG");
+        frame.flags = MagicFlags::from(MagicFlags::INDIRECT);
+        frame.indirection_type = BeId3;
+        frame.indirection_offset = 10;
+        frame.indirection_operation.op = IndirectionOperator::Add;
+        let entries = vec![
+            entry(0, 0, String, Equal, b"ID3", "ID3 tag"),
+            entry(1, 6, BeId3, Anything, &[0], "of %u bytes"),
+            frame,
+        ];
+
+        let mut buf = b"ID3\x04\x00\x00\x00\x00\x01\x00".to_vec();
+        buf.resize(10 + 128, 0);
+        buf.extend_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+        assert_eq!(
+            run(entries, &buf).as_deref(),
+            Some("ID3 tag of 128 bytes MPEG")
+        );
+
+        let entries = vec![entry(0, 0, LeId3, Equal, &[0x81, 0x01], "le")];
+        assert_eq!(run(entries, &[0x01, 0x03, 0, 0]).as_deref(), Some("le"));
+    }
+}
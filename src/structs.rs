@@ -1,4 +1,14 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+use regex::bytes::Regex;
+
+use crate::loader::MAGIC_SETS;
 use crate::magic::Magic;
+use crate::posix;
+use crate::softmagic::TestType;
+use crate::value::ValueType;
 
 // pub struct MagicEntry {
 //     mp: Magic,
@@ -22,61 +32,250 @@ use crate::magic::Magic;
 //
 #[derive(Default)]
 pub struct MagicMap {
-    pub left: Vec<Magic>,
-    pub right: Vec<Magic>,
+    /// The regular entries ordered by strength. Like the name and regex
+    /// caches below, the entries can only be set by `new` so that the
+    /// caches never go stale.
+    left: Vec<Magic>,
+    /// The `name` entries and their continuations, which are only run when
+    /// invoked by a `use` entry.
+    right: Vec<Magic>,
+    /// The index in `right` of each `name` entry keyed by its name.
+    names: HashMap<Vec<u8>, usize>,
+    /// The compiled pattern of every `regex` entry keyed by the pattern and
+    /// whether it ignores case, or the error compiling it.
+    regexes: HashMap<(Vec<u8>, bool), Result<Regex, regex::Error>>,
 }
-//
-// pub struct MagicList {
-//     magic: Vec<Magic>,
-//     regexps: Vec<Vec<u8>>,
-//     nmagic: usize,
-//     // void* map - internal resources used by entry
-//     // mlist* next
-//     // mlist* prev
-// }
-//
-// pub struct LevelInfo {
-//     offset: i32,
-//     got_match: i32,
-//     last_match: i32,
-//     last_cond: i32,
-// }
-//
-// pub struct Context {
-//     len: usize,
-//     level_infos: Vec<LevelInfo>,
-// }
-//
+
+impl MagicMap {
+    pub fn new(left: Vec<Magic>, right: Vec<Magic>) -> Self {
+        let left = sort_by_strength(left);
+        let mut names = HashMap::new();
+        for (idx, m) in right.iter().enumerate() {
+            if matches!(m.value_type, ValueType::Name) {
+                // libmagic uses the first definition of a name.
+                names.entry(m.value.string().to_vec()).or_insert(idx);
+            }
+        }
+
+        let mut regexes = HashMap::new();
+        for m in left.iter().chain(right.iter()) {
+            if matches!(m.value_type, ValueType::Regex) {
+                let (pattern, icase) = regex_key(m);
+                regexes
+                    .entry((pattern.to_vec(), icase))
+                    .or_insert_with(|| posix::compile(pattern, icase));
+            }
+        }
+
+        Self {
+            left,
+            right,
+            names,
+            regexes,
+        }
+    }
+
+    /// The regular entries, ordered from strongest to weakest.
+    pub fn left(&self) -> &[Magic] {
+        &self.left
+    }
+
+    /// The `name` entries and their continuations.
+    pub fn right(&self) -> &[Magic] {
+        &self.right
+    }
+
+    /// The compiled pattern of the `regex` entry `m`, or the error compiling
+    /// it.
+    pub(crate) fn regex(
+        &self,
+        m: &Magic,
+    ) -> Option<&Result<Regex, regex::Error>> {
+        let (pattern, icase) = regex_key(m);
+        self.regexes.get(&(pattern.to_vec(), icase))
+    }
+
+    /// Find the `name` entry called `name` along with its continuations.
+    pub fn named(&self, name: &[u8]) -> Option<&[Magic]> {
+        let start = *self.names.get(name)?;
+        let end = self.right[start + 1..]
+            .iter()
+            .position(|m| m.cont_level == 0)
+            .map_or(self.right.len(), |len| start + 1 + len);
+        Some(&self.right[start..end])
+    }
+
+    /// The entries of set 0 (the regular entries) or set 1 (the `name`
+    /// entries).
+    pub fn set(&self, set: usize) -> &[Magic] {
+        match set {
+            0 => &self.left,
+            _ => &self.right,
+        }
+    }
+
+    /// Write the strength of every top level entry in the format of
+    /// `file -l`.
+    pub fn list<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for set in 0..MAGIC_SETS {
+            writeln!(out, "Set {}:", set)?;
+            writeln!(out, "Binary patterns:")?;
+            for entry in listing(self.set(set), TestType::Binary) {
+                writeln!(out, "{}", entry)?;
+            }
+            writeln!(out, "Text patterns:")?;
+            for entry in listing(self.set(set), TestType::Text) {
+                writeln!(out, "{}", entry)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A top level entry as listed by `file -l`.
+#[derive(Debug)]
+struct Listing<'a> {
+    strength: usize,
+    line_number: u32,
+    desc: &'a str,
+    mimetype: &'a str,
+}
+
+impl fmt::Display for Listing<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Strength = {:3}@{}: {} [{}]",
+            self.strength, self.line_number, self.desc, self.mimetype
+        )
+    }
+}
+
+/// List the top level entries of `entries` evaluated in `test_type`
+/// passes along with their strength, in the order they are evaluated.
+fn listing(entries: &[Magic], test_type: TestType) -> Vec<Listing<'_>> {
+    let mut listing = Vec::new();
+    let mut idx = 0;
+    while idx < entries.len() {
+        let m = &entries[idx];
+        let mut end = idx + 1;
+        while end < entries.len() && entries[end].cont_level != 0 {
+            end += 1;
+        }
+
+        if test_type.includes(m) {
+            // Use the first description and MIME type in the tree as
+            // the top level entry may leave them to its continuations.
+            let tree = &entries[idx..end];
+            let desc = tree.iter().find(|m| !m.desc.is_empty());
+            let mimetype = tree.iter().find(|m| !m.mimetype.is_empty());
+            listing.push(Listing {
+                strength: m.strength(),
+                line_number: m.line_number,
+                desc: desc.map_or("", |m| &m.desc),
+                mimetype: mimetype.map_or("", |m| &m.mimetype),
+            });
+        }
+
+        idx = end;
+    }
+    listing
+}
+
+/// The pattern of a `regex` entry and whether it ignores case.
+fn regex_key(m: &Magic) -> (&[u8], bool) {
+    let flags = m.string_flags();
+    let icase = flags.is_ignore_lowercase() || flags.is_ignore_uppercase();
+    (&m.value.bytes()[..m.value_len as usize], icase)
+}
+
 // pub struct Search {
 //     index: usize,
 //     search_length: usize,
 //     offset: usize,
 //     match_length: usize,
 // }
-//
-// pub struct MagicSet {
-//     mlist: MagicList,
-//     context: Context,
-//     output: String,
-//     offset: u32,
-//     eoffset: u32,
-//     error: i32,
-//     flags: i32,
-//     event_flags: i32,
-//     filename: String,
-//     line: usize,
-//     mode: String, // Probably not a string
-//     warnings: u16,
-//     search: Search,
-//     match_value: Value,
-//     indirection_max: u16,
-//     name_max: u16,
-//     elf_shnum_max: u16,
-//     elf_phnum_max: u16,
-//     elf_notes_max: u16,
-//     regex_max: u16,
-//     num_warnings_max: u16,
-//     bytes_max: usize,
-//     encoding_max: usize,
-//     elf_shsize_max: usize,
-// }
+
+/// Order the top level entries of `entries`, along with their continuations,
+/// from strongest to weakest as libmagic does when compiling a database.
+/// The sort is stable, so entries of equal strength keep their order and a
+/// database compiled by `file -C`, which is already sorted, is unchanged.
+fn sort_by_strength(entries: Vec<Magic>) -> Vec<Magic> {
+    let mut groups: Vec<Vec<Magic>> = Vec::new();
+    for m in entries {
+        match groups.last_mut() {
+            Some(group) if m.cont_level != 0 => group.push(m),
+            _ => groups.push(vec![m]),
+        }
+    }
+
+    groups.sort_by_key(|group| std::cmp::Reverse(group[0].strength()));
+    groups.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::magic::tests::entry;
+    use crate::magic::{MagicFlags, Relation};
+    use crate::value::ValueType;
+
+    #[test]
+    fn check_sort_by_strength() {
+        let entries = [
+            (ValueType::Byte, b"\x01" as &[u8], 0),
+            (ValueType::Byte, b"\x02", 1),
+            (ValueType::String, b"ab", 0),
+            (ValueType::Byte, b"\x03", 0),
+        ];
+        let entries = entries
+            .iter()
+            .enumerate()
+            .map(|(line_number, (vtype, value, cont_level))| {
+                let mut m =
+                    entry(*cont_level, 0, *vtype, Relation::Equal, value, "x");
+                m.line_number = line_number as u32;
+                m
+            })
+            .collect();
+
+        // Entries of equal strength keep their order.
+        let lines: Vec<u32> = sort_by_strength(entries)
+            .iter()
+            .map(|m| m.line_number)
+            .collect();
+        assert_eq!(lines, vec![2, 0, 1, 3]);
+    }
+
+    #[test]
+    fn check_list() {
+        let mut elf =
+            entry(0, 0, ValueType::String, Relation::Equal, b"\x7fELF", "ELF");
+        elf.line_number = 12;
+        let mut version =
+            entry(1, 0, ValueType::Byte, Relation::Equal, &[1], "");
+        version.mimetype = "application/x-elf".to_string();
+        let mut script =
+            entry(0, 0, ValueType::String, Relation::Equal, b"#!", "");
+        script.flags = MagicFlags::from(MagicFlags::TEXT_TEST);
+        script.line_number = 3;
+        let interpreter =
+            entry(1, 0, ValueType::String, Relation::Anything, b"", "script");
+
+        let map =
+            MagicMap::new(vec![script, interpreter, elf, version], vec![]);
+        let mut out = Vec::new();
+        map.list(&mut out).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&out).unwrap(),
+            "Set 0:\n\
+             Binary patterns:\n\
+             Strength =  70@12: ELF [application/x-elf]\n\
+             Text patterns:\n\
+             Strength =  51@3: script []\n\
+             Set 1:\n\
+             Binary patterns:\n\
+             Text patterns:\n"
+        );
+    }
+}
@@ -2,17 +2,83 @@ pub(crate) trait ReadLittleEndian {
     fn read_le(bytes: &[u8]) -> Self;
 }
 
-macro_rules! impl_from_le_bytes (($($type:ty), *) => {
+pub(crate) trait ReadBigEndian {
+    fn read_be(bytes: &[u8]) -> Self;
+}
+
+/// Reads in the byte order of the host, which is what magic types without
+/// an explicit byte order (e.g., `short`, `long` and `quad`) use.
+pub(crate) trait ReadHostOrder {
+    fn read_ne(bytes: &[u8]) -> Self;
+}
+
+/// Reads in the PDP-11 byte order used by `melong`, `medate` and `meldate`
+/// where the two 16-bit halves are big endian but each half is little
+/// endian, i.e., bytes `B1 B0 B3 B2`.
+pub(crate) trait ReadMiddleEndian {
+    fn read_me(bytes: &[u8]) -> Self;
+}
+
+/// Copy the first `N` bytes of `bytes` into an array.
+fn array<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    assert!(bytes.len() >= N);
+    let mut value = [0u8; N];
+    value.copy_from_slice(&bytes[0..N]);
+    value
+}
+
+macro_rules! impl_from_bytes (($($type:ty), *) => {
     $(
         impl ReadLittleEndian for $type {
             fn read_le(bytes: &[u8]) -> Self {
-                assert!(bytes.len() >= std::mem::size_of::<Self>());
-                let mut value = [0u8; std::mem::size_of::<Self>()];
-                value.copy_from_slice(&bytes[0..std::mem::size_of::<Self>()]);
-                Self::from_le_bytes(value)
+                Self::from_le_bytes(array(bytes))
+            }
+        }
+
+        impl ReadBigEndian for $type {
+            fn read_be(bytes: &[u8]) -> Self {
+                Self::from_be_bytes(array(bytes))
+            }
+        }
+
+        impl ReadHostOrder for $type {
+            fn read_ne(bytes: &[u8]) -> Self {
+                Self::from_ne_bytes(array(bytes))
             }
         }
     )*
 });
 
-impl_from_le_bytes!(u8, u16, u32, u64, i8, i16, i32, i64);
+impl_from_bytes!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl ReadMiddleEndian for u32 {
+    fn read_me(bytes: &[u8]) -> Self {
+        let [b0, b1, b2, b3] = array(bytes);
+        u32::from_be_bytes([b1, b0, b3, b2])
+    }
+}
+
+impl ReadMiddleEndian for i32 {
+    fn read_me(bytes: &[u8]) -> Self {
+        u32::read_me(bytes) as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_byte_orders() {
+        let bytes = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        assert_eq!(u16::read_le(&bytes), 0x0201);
+        assert_eq!(u16::read_be(&bytes), 0x0102);
+        assert_eq!(u32::read_le(&bytes), 0x04030201);
+        assert_eq!(u32::read_be(&bytes), 0x01020304);
+        assert_eq!(u32::read_me(&bytes), 0x02010403);
+        assert_eq!(i32::read_me(&[0x00, 0x80, 0x00, 0x00]), i32::MIN);
+        assert_eq!(u64::read_be(&bytes), 0x0102030405060708);
+        assert_eq!(u64::read_ne(&bytes), u64::from_ne_bytes(bytes));
+        assert_eq!(f32::read_be(&1.5f32.to_be_bytes()), 1.5);
+    }
+}
@@ -2,6 +2,8 @@ use std::fmt;
 
 use thiserror::Error;
 
+use crate::traits::ReadLittleEndian;
+
 #[derive(Debug, Error)]
 pub enum ValueError {
     #[error("Invalid value type: {0}")]
@@ -77,7 +79,7 @@ pub enum ValueType {
 }
 
 impl ValueType {
-    fn is_string(&self) -> bool {
+    pub fn is_string(&self) -> bool {
         use ValueType::*;
         matches!(
             self,
@@ -94,6 +96,81 @@ impl ValueType {
                 | Octal
         )
     }
+
+    /// The number of bytes a fixed size type reads, which is eight for the
+    /// variable length integers and `offset` as in libmagic.
+    pub fn size(&self) -> Option<usize> {
+        use ValueType::*;
+        let size = match self {
+            Byte => 1,
+            Short | BeShort | LeShort => 2,
+            MSDosDate | LeMSDosDate | BeMsDosDate => 2,
+            MSDosTime | LeMSDOSTime | BeMSDOSTime => 2,
+            Long | BeLong | LeLong | MeLong => 4,
+            Date | BeDate | LeDate | MeDate => 4,
+            LDate | BeLDate | LeLDate | MeLDate => 4,
+            Float | BeFloat | LeFloat | BeId3 | LeId3 => 4,
+            Quad | BeQuad | LeQuad => 8,
+            QDate | BeQDate | LeQDate | QLDate | BeQLDate | LeQLDate => 8,
+            QwDate | BeQwDate | LeQwDate => 8,
+            Double | BeDouble | LeDouble => 8,
+            Offset | BeVarInt | LeVarInt => 8,
+            Guid => 16,
+            _ => return None,
+        };
+        Some(size)
+    }
+
+    /// Whether this is a fixed size integer type, which includes the dates
+    /// but not the variable length integers.
+    pub fn is_integer(&self) -> bool {
+        use ValueType::*;
+        self.size().is_some()
+            && !self.is_float()
+            && !matches!(self, Offset | BeVarInt | LeVarInt | Guid)
+    }
+
+    /// Whether this is a floating point type.
+    pub fn is_float(&self) -> bool {
+        use ValueType::*;
+        matches!(
+            self,
+            Float | BeFloat | LeFloat | Double | BeDouble | LeDouble
+        )
+    }
+
+    /// The same type with the opposite byte order, as used inside a named
+    /// subroutine called with `use ^name`. Host order types are unchanged.
+    pub fn flipped(&self) -> Self {
+        use ValueType::*;
+        match self {
+            BeShort => LeShort,
+            BeLong => LeLong,
+            BeDate => LeDate,
+            BeLDate => LeLDate,
+            BeQuad => LeQuad,
+            BeQDate => LeQDate,
+            BeQLDate => LeQLDate,
+            BeQwDate => LeQwDate,
+            BeFloat => LeFloat,
+            BeDouble => LeDouble,
+            BeMsDosDate => LeMSDosDate,
+            BeMSDOSTime => LeMSDOSTime,
+            LeShort => BeShort,
+            LeLong => BeLong,
+            LeDate => BeDate,
+            LeLDate => BeLDate,
+            LeQuad => BeQuad,
+            LeQDate => BeQDate,
+            LeQLDate => BeQLDate,
+            LeQwDate => BeQwDate,
+            LeFloat => BeFloat,
+            LeDouble => BeDouble,
+            LeMSDosDate => BeMsDosDate,
+            LeMSDOSTime => BeMSDOSTime,
+            other => *other,
+        }
+    }
 }
 
 impl TryFrom<u8> for ValueType {
@@ -173,7 +250,7 @@ impl TryFrom<u8> for ValueType {
 #[derive(Debug)]
 pub enum ValueOption {
     Numeric { mask: u64 },
-    String { count: u32, flags: u32 },
+    String { count: u32, flags: StringFlags },
 }
 
 impl Default for ValueOption {
@@ -182,6 +259,266 @@ impl Default for ValueOption {
     }
 }
 
+/// The modifiers of a string type test, e.g., the `Wc` in `string/Wc`.
+/// Some bits have a different meaning depending on the type of the test.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct StringFlags {
+    flags: u32,
+}
+
+impl StringFlags {
+    pub(crate) const COMPACT_WHITESPACE: u32 = 1 << 0;
+    pub(crate) const INDIRECT_RELATIVE: u32 = 1 << 0;
+    pub(crate) const OPTIONAL_WHITESPACE: u32 = 1 << 1;
+    pub(crate) const IGNORE_LOWERCASE: u32 = 1 << 2;
+    pub(crate) const IGNORE_UPPERCASE: u32 = 1 << 3;
+    pub(crate) const REGEX_OFFSET_START: u32 = 1 << 4;
+    pub(crate) const TEXT_TEST: u32 = 1 << 5;
+    pub(crate) const BIN_TEST: u32 = 1 << 6;
+    pub(crate) const PSTRING_1: u32 = 1 << 7;
+    pub(crate) const PSTRING_2_BE: u32 = 1 << 8;
+    pub(crate) const PSTRING_2_LE: u32 = 1 << 9;
+    pub(crate) const PSTRING_4_BE: u32 = 1 << 10;
+    pub(crate) const PSTRING_4_LE: u32 = 1 << 11;
+    pub(crate) const REGEX_LINE_COUNT: u32 = 1 << 11;
+    pub(crate) const PSTRING_LENGTH_INCLUDES_ITSELF: u32 = 1 << 12;
+    pub(crate) const TRIM: u32 = 1 << 13;
+    pub(crate) const FULL_WORD: u32 = 1 << 14;
+
+    const PSTRING_LEN: u32 = Self::PSTRING_1
+        | Self::PSTRING_2_BE
+        | Self::PSTRING_2_LE
+        | Self::PSTRING_4_BE
+        | Self::PSTRING_4_LE;
+
+    /// `W`: a whitespace in the pattern matches one or more in the data.
+    pub fn is_compact_whitespace(&self) -> bool {
+        self.is_set(Self::COMPACT_WHITESPACE)
+    }
+
+    /// `r`: the offset of an `indirect` test is relative to the `use` entry
+    /// that called the current named subroutine. This shares a bit with `W`.
+    pub fn is_indirect_relative(&self) -> bool {
+        self.is_set(Self::INDIRECT_RELATIVE)
+    }
+
+    /// `w`: a whitespace in the pattern matches zero or more in the data.
+    pub fn is_optional_whitespace(&self) -> bool {
+        self.is_set(Self::OPTIONAL_WHITESPACE)
+    }
+
+    /// `c`: lower case characters in the pattern match either case.
+    pub fn is_ignore_lowercase(&self) -> bool {
+        self.is_set(Self::IGNORE_LOWERCASE)
+    }
+
+    /// `C`: upper case characters in the pattern match either case.
+    pub fn is_ignore_uppercase(&self) -> bool {
+        self.is_set(Self::IGNORE_UPPERCASE)
+    }
+
+    /// `s`: the offset for the next level is the start of a regex or
+    /// search match instead of its end.
+    pub fn is_regex_offset_start(&self) -> bool {
+        self.is_set(Self::REGEX_OFFSET_START)
+    }
+
+    /// `t`: the test only applies to text files.
+    pub fn is_text_test(&self) -> bool {
+        self.is_set(Self::TEXT_TEST)
+    }
+
+    /// `b`: the test only applies to binary files.
+    pub fn is_bin_test(&self) -> bool {
+        self.is_set(Self::BIN_TEST)
+    }
+
+    /// `l`: the range of a regex test is in lines rather than bytes. This
+    /// shares a bit with `L` of a `pstring`.
+    pub fn is_regex_line_count(&self) -> bool {
+        self.is_set(Self::REGEX_LINE_COUNT)
+    }
+
+    /// The number of bytes of the length prefix of a `pstring`, selected by
+    /// `B`, `H`, `h`, `L` or `l`. Defaults to a single byte.
+    pub fn pstring_length_size(&self) -> usize {
+        match self.flags & Self::PSTRING_LEN {
+            Self::PSTRING_2_BE | Self::PSTRING_2_LE => 2,
+            Self::PSTRING_4_BE | Self::PSTRING_4_LE => 4,
+            _ => 1,
+        }
+    }
+
+    /// Whether the length prefix of a `pstring` is little endian.
+    pub fn is_pstring_length_le(&self) -> bool {
+        matches!(
+            self.flags & Self::PSTRING_LEN,
+            Self::PSTRING_2_LE | Self::PSTRING_4_LE
+        )
+    }
+
+    /// `J`: the length prefix of a `pstring` counts its own bytes.
+    pub fn is_pstring_length_includes_itself(&self) -> bool {
+        self.is_set(Self::PSTRING_LENGTH_INCLUDES_ITSELF)
+    }
+
+    /// `T`: leading and trailing whitespace is trimmed from the output.
+    pub fn is_trim(&self) -> bool {
+        self.is_set(Self::TRIM)
+    }
+
+    /// `f`: the match has to be a whole word.
+    pub fn is_full_word(&self) -> bool {
+        self.is_set(Self::FULL_WORD)
+    }
+
+    pub fn is_set(&self, flag: u32) -> bool {
+        self.flags & flag == flag
+    }
+}
+
+impl From<u32> for StringFlags {
+    fn from(value: u32) -> Self {
+        Self { flags: value }
+    }
+}
+
+impl fmt::Debug for StringFlags {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let names = [
+            // Which of the two this is depends on the type of the test.
+            (
+                Self::COMPACT_WHITESPACE,
+                "COMPACT_WHITESPACE/INDIRECT_RELATIVE",
+            ),
+            (Self::OPTIONAL_WHITESPACE, "OPTIONAL_WHITESPACE"),
+            (Self::IGNORE_LOWERCASE, "IGNORE_LOWERCASE"),
+            (Self::IGNORE_UPPERCASE, "IGNORE_UPPERCASE"),
+            (Self::REGEX_OFFSET_START, "REGEX_OFFSET_START"),
+            (Self::TEXT_TEST, "TEXT_TEST"),
+            (Self::BIN_TEST, "BIN_TEST"),
+            (Self::PSTRING_1, "PSTRING_1"),
+            (Self::PSTRING_2_BE, "PSTRING_2_BE"),
+            (Self::PSTRING_2_LE, "PSTRING_2_LE"),
+            (Self::PSTRING_4_BE, "PSTRING_4_BE"),
+            // Which of the two this is depends on the type of the test.
+            (Self::PSTRING_4_LE, "PSTRING_4_LE/REGEX_LINE_COUNT"),
+            (
+                Self::PSTRING_LENGTH_INCLUDES_ITSELF,
+                "PSTRING_LENGTH_INCLUDES_ITSELF",
+            ),
+            (Self::TRIM, "TRIM"),
+            (Self::FULL_WORD, "FULL_WORD"),
+        ];
+
+        let mut str_flags: Vec<&str> = names
+            .iter()
+            .filter(|(flag, _)| self.is_set(*flag))
+            .map(|(_, name)| *name)
+            .collect();
+
+        if str_flags.is_empty() {
+            str_flags.push("N/A");
+        }
+
+        fmt.write_fmt(format_args!("{}", str_flags.join(" | ")))
+    }
+}
+
+/// The value of a numeric test decoded according to its type.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NumericValue {
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+impl NumericValue {
+    /// The value widened to 64 bits the way libmagic compares integers,
+    /// i.e., sign extended unless it's unsigned. Floats have no such
+    /// representation.
+    pub fn as_integer(&self) -> Option<u64> {
+        use NumericValue::*;
+        let value = match *self {
+            U8(v) => v as u64,
+            I8(v) => v as i64 as u64,
+            U16(v) => v as u64,
+            I16(v) => v as i64 as u64,
+            U32(v) => v as u64,
+            I32(v) => v as i64 as u64,
+            U64(v) => v,
+            I64(v) => v as u64,
+            F32(_) | F64(_) => return None,
+        };
+        Some(value)
+    }
+}
+
+impl fmt::Display for NumericValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use NumericValue::*;
+        match self {
+            U8(v) => write!(f, "{}", v),
+            I8(v) => write!(f, "{}", v),
+            U16(v) => write!(f, "{}", v),
+            I16(v) => write!(f, "{}", v),
+            U32(v) => write!(f, "{}", v),
+            I32(v) => write!(f, "{}", v),
+            U64(v) => write!(f, "{}", v),
+            I64(v) => write!(f, "{}", v),
+            F32(v) => write!(f, "{}", v),
+            F64(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// A GUID as laid out in memory on Windows, i.e., the first three fields
+/// are little endian while the last eight bytes are in order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Guid {
+    bytes: [u8; 16],
+}
+
+impl Guid {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut guid = [0u8; 16];
+        guid.copy_from_slice(&bytes[..16]);
+        Guid { bytes: guid }
+    }
+
+    /// The bytes of the GUID as stored, which is what libmagic compares.
+    pub fn bytes(&self) -> &[u8; 16] {
+        &self.bytes
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.bytes;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-",
+            u32::read_le(&b[0..4]),
+            u16::read_le(&b[4..6]),
+            u16::read_le(&b[6..8])
+        )?;
+        for (idx, byte) in b[8..].iter().enumerate() {
+            if idx == 2 {
+                f.write_str("-")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
 pub struct Value {
     vtype: ValueType,
     bytes: Box<[u8]>,
@@ -194,6 +531,9 @@ impl Value {
         // Trust length if it's non-zero
         let len = if len != 0 {
             len as usize
+        } else if matches!(vtype, ValueType::Guid) {
+            // A GUID may well end with zero bytes.
+            16
         } else {
             let mut len = bytes.len() - 1;
             // > 1 here because we always want at least a single byte even if
@@ -210,6 +550,71 @@ impl Value {
         let bytes = Vec::from(&bytes[0..len]).into_boxed_slice();
         Ok(Value { vtype, bytes })
     }
+
+    pub fn value_type(&self) -> ValueType {
+        self.vtype
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Decode a numeric value, or return `None` for the other types.
+    ///
+    /// N.B., the compiler stores every numeric value as a 64 bit integer
+    /// (or a float or double) in the byte order of the database whatever
+    /// the byte order of the data it's compared against. We only load
+    /// little endian databases so big and middle endian types such as
+    /// `MeLong` are decoded exactly like their host order counterparts.
+    pub fn numeric(&self, unsigned: bool) -> Option<NumericValue> {
+        use ValueType::*;
+
+        let mut bytes = [0u8; 8];
+        let len = self.bytes.len().min(8);
+        bytes[..len].copy_from_slice(&self.bytes[..len]);
+
+        let value = match self.vtype {
+            Float | BeFloat | LeFloat => {
+                NumericValue::F32(f32::read_le(&bytes))
+            }
+            Double | BeDouble | LeDouble => {
+                NumericValue::F64(f64::read_le(&bytes))
+            }
+            _ => {
+                let value = u64::read_le(&bytes);
+                match (self.vtype.size()?, unsigned) {
+                    (1, true) => NumericValue::U8(value as u8),
+                    (1, false) => NumericValue::I8(value as i8),
+                    (2, true) => NumericValue::U16(value as u16),
+                    (2, false) => NumericValue::I16(value as i16),
+                    (4, true) => NumericValue::U32(value as u32),
+                    (4, false) => NumericValue::I32(value as i32),
+                    (8, true) => NumericValue::U64(value),
+                    (8, false) => NumericValue::I64(value as i64),
+                    _ => return None,
+                }
+            }
+        };
+        Some(value)
+    }
+
+    /// The value of a `guid` test, or `None` for the other types.
+    pub fn guid(&self) -> Option<Guid> {
+        match self.vtype {
+            ValueType::Guid => Some(Guid::from_bytes(&self.bytes)),
+            _ => None,
+        }
+    }
+
+    /// The bytes of a string value up to its NUL terminator.
+    pub fn string(&self) -> &[u8] {
+        let end = self
+            .bytes
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(self.bytes.len());
+        &self.bytes[..end]
+    }
 }
 
 impl fmt::Debug for Value {
@@ -226,17 +631,12 @@ impl fmt::Debug for Value {
                     self.vtype, self.bytes
                 ))?;
             }
+        } else if let Some(value) = self.numeric(true) {
+            f.write_fmt(format_args!("{:?}: {}", self.vtype, value))?;
+        } else if let Some(guid) = self.guid() {
+            f.write_fmt(format_args!("{:?}: {}", self.vtype, guid))?;
         } else {
-            match self.vtype {
-                ValueType::Byte => f.write_fmt(format_args!(
-                    "{:?}: {:?}",
-                    self.vtype, self.bytes[0]
-                ))?,
-                _ => f.write_fmt(format_args!(
-                    "{:?}: {:?}",
-                    self.vtype, self.bytes
-                ))?,
-            };
+            f.write_fmt(format_args!("{:?}: {:?}", self.vtype, self.bytes))?;
         }
 
         Ok(())
@@ -260,4 +660,111 @@ mod tests {
             assert!(vt.ok().unwrap() as u8 == i);
         }
     }
+
+    #[test]
+    fn check_type_sizes() {
+        use ValueType::*;
+
+        assert_eq!(BeId3.size(), Some(4));
+        assert!(BeId3.is_integer());
+        assert!(QwDate.is_integer());
+        assert!(LeDouble.is_float() && !LeDouble.is_integer());
+        assert_eq!(LeVarInt.size(), Some(8));
+        assert!(!LeVarInt.is_integer());
+        assert_eq!(Guid.size(), Some(16));
+        assert!(!Guid.is_integer());
+        assert_eq!(String.size(), None);
+        assert!(!String.is_integer());
+    }
+
+    /// Build a numeric value the way the loader does from the value field of
+    /// a record.
+    fn numeric(vtype: ValueType, value: &[u8]) -> Value {
+        let mut bytes = [0u8; 128];
+        bytes[..value.len()].copy_from_slice(value);
+        Value::new(vtype, 0, &bytes).unwrap()
+    }
+
+    #[test]
+    fn check_numeric_values() {
+        use ValueType::*;
+
+        let minus_one = (-1i64).to_le_bytes();
+        let value = numeric(Long, &minus_one);
+        assert_eq!(value.numeric(false), Some(NumericValue::I32(-1)));
+        assert_eq!(value.numeric(true), Some(NumericValue::U32(u32::MAX)));
+        assert_eq!(value.numeric(false).unwrap().as_integer(), Some(u64::MAX));
+        assert_eq!(value.numeric(true).unwrap().as_integer(), Some(0xffffffff));
+
+        let value = numeric(MeLong, &0x12345678u64.to_le_bytes());
+        assert_eq!(value.numeric(true), Some(NumericValue::U32(0x12345678)));
+
+        let value = numeric(Byte, &[0x80, 0, 0, 0]);
+        assert_eq!(value.numeric(false), Some(NumericValue::I8(-128)));
+        assert_eq!(format!("{:?}", value), "Byte: 128");
+
+        let value = numeric(BeFloat, &1.5f32.to_le_bytes());
+        assert_eq!(value.numeric(false), Some(NumericValue::F32(1.5)));
+        assert_eq!(value.numeric(false).unwrap().as_integer(), None);
+
+        let value = numeric(LeDouble, &(-0.25f64).to_le_bytes());
+        assert_eq!(value.numeric(false), Some(NumericValue::F64(-0.25)));
+
+        let value = Value::new(String, 3, b"abc").unwrap();
+        assert_eq!(value.numeric(false), None);
+    }
+
+    #[test]
+    fn check_string_flags() {
+        let flags = StringFlags::from(0);
+        assert_eq!(format!("{:?}", flags), "N/A");
+        assert_eq!(flags.pstring_length_size(), 1);
+        assert!(!flags.is_pstring_length_le());
+
+        // string/Wct
+        let flags = StringFlags::from(0x25);
+        assert!(flags.is_compact_whitespace());
+        assert!(flags.is_ignore_lowercase());
+        assert!(flags.is_text_test());
+        assert!(!flags.is_bin_test());
+        assert_eq!(
+            format!("{:?}", flags),
+            "COMPACT_WHITESPACE/INDIRECT_RELATIVE | IGNORE_LOWERCASE | \
+             TEXT_TEST"
+        );
+
+        // indirect/r
+        assert!(StringFlags::from(0x01).is_indirect_relative());
+
+        // pstring/HJ
+        let flags = StringFlags::from(0x1100);
+        assert_eq!(flags.pstring_length_size(), 2);
+        assert!(!flags.is_pstring_length_le());
+        assert!(flags.is_pstring_length_includes_itself());
+
+        // pstring/l
+        let flags = StringFlags::from(0x800);
+        assert_eq!(flags.pstring_length_size(), 4);
+        assert!(flags.is_pstring_length_le());
+        assert!(flags.is_regex_line_count());
+    }
+
+    #[test]
+    fn check_guids() {
+        // The ASF header object
+        let bytes = [
+            0x30, 0x26, 0xb2, 0x75, 0x8e, 0x66, 0xcf, 0x11, 0xa6, 0xd9, 0x00,
+            0xaa, 0x00, 0x62, 0xce, 0x6c,
+        ];
+        let guid = Guid::from_bytes(&bytes);
+        assert_eq!(guid.to_string(), "75B22630-668E-11CF-A6D9-00AA0062CE6C");
+
+        let mut padded = [0u8; 128];
+        padded[..14].copy_from_slice(&bytes[..14]);
+        let value = Value::new(ValueType::Guid, 0, &padded).unwrap();
+        assert_eq!(
+            format!("{:?}", value),
+            "Guid: 75B22630-668E-11CF-A6D9-00AA00620000"
+        );
+    }
 }